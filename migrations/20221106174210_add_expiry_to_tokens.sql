@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Existing tokens have no known creation time, so they are given a fresh day to be used.
+BEGIN;
+	ALTER TABLE tokens
+		ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+	ALTER TABLE tokens
+		ADD COLUMN expires_at timestamptz NULL;
+	UPDATE tokens
+		SET expires_at = created_at + interval '1 day'
+		WHERE expires_at IS NULL;
+	ALTER TABLE tokens
+		ALTER COLUMN expires_at SET NOT NULL;
+COMMIT;
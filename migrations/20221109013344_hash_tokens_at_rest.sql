@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Tokens are now stored as a keyed digest. The secret is not available to the migration, so rows
+-- written before this point still hold the plaintext token and are flagged as not hashed until
+-- the application converts them at startup.
+BEGIN;
+	ALTER TABLE tokens
+		RENAME COLUMN subscription_token TO token_hash;
+	ALTER TABLE tokens
+		ADD COLUMN hashed BOOLEAN NOT NULL DEFAULT false;
+COMMIT;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues(
+	newsletter_issue_id uuid NOT NULL,
+	PRIMARY KEY (newsletter_issue_id),
+	title TEXT NOT NULL,
+	body_text TEXT NOT NULL,
+	body_html TEXT NOT NULL,
+	published_at timestamptz NOT NULL
+);
+CREATE TABLE issue_delivery_queue(
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_email TEXT NOT NULL,
+	PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
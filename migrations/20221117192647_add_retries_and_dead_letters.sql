@@ -0,0 +1,21 @@
+-- Add migration script here
+BEGIN;
+	ALTER TABLE issue_delivery_queue
+		ADD COLUMN n_retries INT NOT NULL DEFAULT 0;
+	ALTER TABLE issue_delivery_queue
+		ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+	CREATE TABLE email_dead_letters(
+		dead_letter_id uuid NOT NULL,
+		PRIMARY KEY (dead_letter_id),
+		recipient TEXT NOT NULL,
+		subject TEXT NOT NULL,
+		body_text TEXT NOT NULL,
+		body_html TEXT NOT NULL,
+		newsletter_issue_id uuid NULL
+			REFERENCES newsletter_issues (newsletter_issue_id),
+		attempts INT NOT NULL,
+		last_error TEXT NOT NULL,
+		failed_at timestamptz NOT NULL,
+		replayed_at timestamptz NULL
+	);
+COMMIT;
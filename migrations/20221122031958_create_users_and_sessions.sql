@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE users(
+	user_id uuid NOT NULL,
+	PRIMARY KEY (user_id),
+	username TEXT NOT NULL UNIQUE,
+	password_hash TEXT NOT NULL
+);
+-- Only a hash of each session key is stored, so the table can't be used to hijack sessions.
+CREATE TABLE sessions(
+	session_key_hash TEXT NOT NULL,
+	PRIMARY KEY (session_key_hash),
+	state TEXT NOT NULL,
+	expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Token buckets used to rate limit subscriptions, keyed by client IP or target email.
+CREATE TABLE rate_limit_buckets(
+	key TEXT NOT NULL,
+	PRIMARY KEY (key),
+	tokens DOUBLE PRECISION NOT NULL,
+	updated_at timestamptz NOT NULL,
+	-- When the bucket will have refilled completely, after which the row can be dropped.
+	full_at timestamptz NOT NULL
+);
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
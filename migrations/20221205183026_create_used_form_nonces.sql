@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Form nonces that have already been submitted, kept until they would have expired anyway.
+CREATE TABLE used_form_nonces(
+	nonce_hash TEXT NOT NULL,
+	PRIMARY KEY (nonce_hash),
+	expires_at timestamptz NOT NULL
+);
+CREATE INDEX used_form_nonces_expires_at_idx ON used_form_nonces (expires_at);
@@ -0,0 +1,34 @@
+-- Add migration script here
+-- Addresses are now unique by their canonical, lowercased form rather than as typed. Domains
+-- can't be IDNA-encoded here, so existing rows only have their domain lowercased; new addresses
+-- are fully normalised by the application.
+BEGIN;
+	ALTER TABLE subscriptions
+		DROP CONSTRAINT subscriptions_email_key;
+	UPDATE subscriptions
+		SET email = regexp_replace(trim(email), '@[^@]*$', '')
+			|| '@' || lower(substring(trim(email) from '@([^@]*)$'))
+		WHERE email ~ '@[^@]*$';
+	ALTER TABLE subscriptions
+		ADD COLUMN email_canonical TEXT NULL;
+	UPDATE subscriptions
+		SET email_canonical = lower(email);
+	-- Keep one subscription per address: a confirmed one if there is any, otherwise the oldest.
+	CREATE TEMPORARY TABLE duplicate_subscriptions ON COMMIT DROP AS
+		SELECT id FROM (
+			SELECT id, row_number() OVER (
+				PARTITION BY email_canonical
+				ORDER BY (status = 'confirmed') DESC, subscribed_at ASC
+			) AS position
+			FROM subscriptions
+		) ranked
+		WHERE position > 1;
+	DELETE FROM tokens
+		WHERE subscriber_id IN (SELECT id FROM duplicate_subscriptions);
+	DELETE FROM subscriptions
+		WHERE id IN (SELECT id FROM duplicate_subscriptions);
+	ALTER TABLE subscriptions
+		ALTER COLUMN email_canonical SET NOT NULL;
+	ALTER TABLE subscriptions
+		ADD CONSTRAINT subscriptions_email_canonical_key UNIQUE (email_canonical);
+COMMIT;
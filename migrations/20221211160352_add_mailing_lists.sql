@@ -0,0 +1,48 @@
+-- Add migration script here
+-- Subscribers can now join several mailing lists. `subscriptions` keeps one row per address,
+-- while the status of each subscription moves to `list_subscriptions`. Everything that existed
+-- before belongs to the default list.
+BEGIN;
+	CREATE TABLE lists(
+		list_id uuid NOT NULL,
+		PRIMARY KEY (list_id),
+		slug TEXT NOT NULL UNIQUE,
+		name TEXT NOT NULL,
+		created_at timestamptz NOT NULL
+	);
+	INSERT INTO lists (list_id, slug, name, created_at)
+		VALUES ('00000000-0000-0000-0000-000000000001', 'default', 'Newsletter', now());
+
+	CREATE TABLE list_subscriptions(
+		list_id uuid NOT NULL
+			REFERENCES lists (list_id),
+		subscriber_id uuid NOT NULL
+			REFERENCES subscriptions (id),
+		PRIMARY KEY (list_id, subscriber_id),
+		status TEXT NOT NULL,
+		subscribed_at timestamptz NOT NULL,
+		unsubscribed_at timestamptz NULL
+	);
+	INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, unsubscribed_at)
+		SELECT '00000000-0000-0000-0000-000000000001', id, status, subscribed_at, unsubscribed_at
+		FROM subscriptions;
+	ALTER TABLE subscriptions
+		DROP COLUMN status;
+	ALTER TABLE subscriptions
+		DROP COLUMN unsubscribed_at;
+
+	-- Tokens confirm or cancel the subscription to a single list.
+	ALTER TABLE tokens
+		ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+	UPDATE tokens
+		SET list_id = '00000000-0000-0000-0000-000000000001';
+	ALTER TABLE tokens
+		ALTER COLUMN list_id SET NOT NULL;
+
+	ALTER TABLE newsletter_issues
+		ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+	UPDATE newsletter_issues
+		SET list_id = '00000000-0000-0000-0000-000000000001';
+	ALTER TABLE newsletter_issues
+		ALTER COLUMN list_id SET NOT NULL;
+COMMIT;
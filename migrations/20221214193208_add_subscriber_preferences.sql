@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Confirmed subscribers manage their preferences through a link holding a management token.
+-- Each subscriber has at most one token at a time, stored as a keyed hash like the subscription
+-- tokens.
+BEGIN;
+	CREATE TABLE management_tokens(
+		token_hash TEXT NOT NULL,
+		PRIMARY KEY (token_hash),
+		subscriber_id uuid NOT NULL UNIQUE
+			REFERENCES subscriptions (id),
+		created_at timestamptz NOT NULL
+	);
+	-- Newsletters aren't queued for subscribers whose delivery is paused.
+	ALTER TABLE subscriptions
+		ADD COLUMN paused_until timestamptz NULL;
+COMMIT;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Tokens now either confirm a subscription to a list, or a change of the subscriber's address
+-- to `new_email`.
+BEGIN;
+	ALTER TABLE tokens
+		ADD COLUMN new_email TEXT NULL;
+	ALTER TABLE tokens
+		ALTER COLUMN list_id DROP NOT NULL;
+	ALTER TABLE tokens
+		ADD CONSTRAINT tokens_purpose_check CHECK ((list_id IS NULL) <> (new_email IS NULL));
+COMMIT;
@@ -0,0 +1,31 @@
+-- Add migration script here
+-- Erasing a subscriber deletes their row along with everything hanging off it, and leaves a
+-- tombstone behind. The tombstone only keeps a keyed hash of the address, which is enough to
+-- tell whether an address was erased without holding on to it.
+BEGIN;
+	ALTER TABLE tokens
+		DROP CONSTRAINT tokens_subscriber_id_fkey,
+		ADD CONSTRAINT tokens_subscriber_id_fkey FOREIGN KEY (subscriber_id)
+			REFERENCES subscriptions (id) ON DELETE CASCADE;
+	ALTER TABLE list_subscriptions
+		DROP CONSTRAINT list_subscriptions_subscriber_id_fkey,
+		ADD CONSTRAINT list_subscriptions_subscriber_id_fkey FOREIGN KEY (subscriber_id)
+			REFERENCES subscriptions (id) ON DELETE CASCADE;
+	ALTER TABLE management_tokens
+		DROP CONSTRAINT management_tokens_subscriber_id_fkey,
+		ADD CONSTRAINT management_tokens_subscriber_id_fkey FOREIGN KEY (subscriber_id)
+			REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+	CREATE TABLE erased_subscribers(
+		tombstone_id uuid NOT NULL,
+		PRIMARY KEY (tombstone_id),
+		subscriber_id uuid NOT NULL,
+		email_hash TEXT NOT NULL,
+		-- 'subscriber' when erased through the management link, 'admin' otherwise.
+		requested_by TEXT NOT NULL,
+		admin_user_id uuid NULL
+			REFERENCES users (user_id),
+		erased_at timestamptz NOT NULL
+	);
+	CREATE INDEX erased_subscribers_email_hash_idx ON erased_subscribers (email_hash);
+COMMIT;
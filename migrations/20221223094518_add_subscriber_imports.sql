@@ -0,0 +1,48 @@
+-- Add migration script here
+-- Subscribers can be imported in bulk from a CSV. Each import is recorded along with the rows it
+-- couldn't take, and subscriptions remember the import that created them, so that an attestation
+-- of consent can be traced back for subscribers imported as confirmed. Subscribers imported as
+-- pending get their confirmation email from a queue rather than during the import.
+BEGIN;
+	CREATE TABLE subscriber_imports(
+		import_id uuid NOT NULL,
+		PRIMARY KEY (import_id),
+		list_id uuid NOT NULL
+			REFERENCES lists (list_id),
+		-- 'confirmed' or 'pending': the status new subscriptions are given.
+		status TEXT NOT NULL,
+		-- Where the consent of subscribers imported as confirmed was collected.
+		attestation TEXT NULL,
+		-- NULL when the import was run from the command line.
+		admin_user_id uuid NULL
+			REFERENCES users (user_id),
+		started_at timestamptz NOT NULL,
+		finished_at timestamptz NULL,
+		imported_rows INT NOT NULL DEFAULT 0,
+		failed_rows INT NOT NULL DEFAULT 0,
+		CONSTRAINT subscriber_imports_attestation_check CHECK (
+			(status = 'confirmed' AND attestation IS NOT NULL)
+			OR (status = 'pending' AND attestation IS NULL)
+		)
+	);
+	CREATE TABLE subscriber_import_errors(
+		import_id uuid NOT NULL
+			REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+		line BIGINT NOT NULL,
+		PRIMARY KEY (import_id, line),
+		email TEXT NOT NULL,
+		error TEXT NOT NULL
+	);
+	ALTER TABLE list_subscriptions
+		ADD COLUMN import_id uuid NULL
+			REFERENCES subscriber_imports (import_id);
+	CREATE TABLE confirmation_email_queue(
+		subscriber_id uuid NOT NULL
+			REFERENCES subscriptions (id) ON DELETE CASCADE,
+		list_id uuid NOT NULL
+			REFERENCES lists (list_id),
+		PRIMARY KEY (subscriber_id, list_id),
+		n_retries INT NOT NULL DEFAULT 0,
+		execute_after timestamptz NOT NULL DEFAULT now()
+	);
+COMMIT;
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Newsletter issues carry a signed unsubscribe link for each recipient, which needs the ID of
+-- the subscriber a delivery task is for. Tasks queued before this are matched to their subscriber
+-- through the address; any left without one can't be delivered and are dropped.
+BEGIN;
+	ALTER TABLE issue_delivery_queue
+		ADD COLUMN subscriber_id uuid NULL
+			REFERENCES subscriptions (id) ON DELETE CASCADE;
+	UPDATE issue_delivery_queue q
+		SET subscriber_id = s.id
+		FROM subscriptions s
+		WHERE lower(q.subscriber_email) = s.email_canonical;
+	DELETE FROM issue_delivery_queue
+		WHERE subscriber_id IS NULL;
+	ALTER TABLE issue_delivery_queue
+		ALTER COLUMN subscriber_id SET NOT NULL;
+COMMIT;
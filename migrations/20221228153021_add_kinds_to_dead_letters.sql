@@ -0,0 +1,47 @@
+-- Add migration script here
+-- Emails carrying a token link are no longer kept with their bodies, since anyone listing dead
+-- letters could follow the link. Each dead letter records what kind of email it holds instead,
+-- along with what is needed to render it again, with a fresh token, when it is replayed. Only
+-- notices, which carry no links, keep their bodies.
+BEGIN;
+	ALTER TABLE email_dead_letters
+		ADD COLUMN kind TEXT NULL,
+		ADD COLUMN subscriber_id uuid NULL
+			REFERENCES subscriptions (id) ON DELETE CASCADE,
+		ADD COLUMN list_id uuid NULL
+			REFERENCES lists (list_id),
+		ALTER COLUMN body_text DROP NOT NULL,
+		ALTER COLUMN body_html DROP NOT NULL;
+	-- Newsletter issues can be rendered again for their recipient.
+	UPDATE email_dead_letters d
+		SET kind = 'newsletter', subscriber_id = s.id
+		FROM subscriptions s
+		WHERE d.newsletter_issue_id IS NOT NULL
+			AND lower(d.recipient) = s.email_canonical;
+	-- Confirmations stored so far can't be told apart from each other reliably, so anything
+	-- else holding a token link is redacted and can no longer be replayed.
+	UPDATE email_dead_letters
+		SET kind = 'redacted'
+		WHERE kind IS NULL
+			AND (newsletter_issue_id IS NOT NULL
+				OR body_text LIKE '%token=%'
+				OR body_html LIKE '%token=%');
+	UPDATE email_dead_letters
+		SET kind = 'notice'
+		WHERE kind IS NULL;
+	UPDATE email_dead_letters
+		SET body_text = NULL, body_html = NULL
+		WHERE kind <> 'notice';
+	ALTER TABLE email_dead_letters
+		ALTER COLUMN kind SET NOT NULL,
+		ADD CONSTRAINT email_dead_letters_kind_check CHECK (
+			(kind = 'notice' AND body_text IS NOT NULL AND body_html IS NOT NULL)
+			OR (kind <> 'notice' AND body_text IS NULL AND body_html IS NULL AND (
+				(kind = 'newsletter'
+					AND newsletter_issue_id IS NOT NULL AND subscriber_id IS NOT NULL)
+				OR (kind = 'confirmation' AND subscriber_id IS NOT NULL AND list_id IS NOT NULL)
+				OR (kind = 'email_change' AND subscriber_id IS NOT NULL)
+				OR kind = 'redacted'
+			))
+		);
+COMMIT;
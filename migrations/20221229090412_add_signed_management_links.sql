@@ -0,0 +1,28 @@
+-- Add migration script here
+-- Management links no longer change every time a subscriber confirms a list. A link is now
+-- signed over the subscriber's ID and the link ID stored here, so the same link can be rendered
+-- for every email, and replacing the link ID revokes it. Links handed out before keep working
+-- through their stored hash; those subscribers get a link ID the next time one is needed.
+BEGIN;
+	ALTER TABLE management_tokens
+		DROP CONSTRAINT management_tokens_pkey,
+		DROP CONSTRAINT management_tokens_subscriber_id_key,
+		ADD PRIMARY KEY (subscriber_id),
+		ALTER COLUMN token_hash DROP NOT NULL,
+		ADD CONSTRAINT management_tokens_token_hash_key UNIQUE (token_hash),
+		ADD COLUMN link_id uuid NULL;
+	-- Confirming a subscription sends the management link, which is rendered again on replay.
+	ALTER TABLE email_dead_letters
+		DROP CONSTRAINT email_dead_letters_kind_check,
+		ADD CONSTRAINT email_dead_letters_kind_check CHECK (
+			(kind = 'notice' AND body_text IS NOT NULL AND body_html IS NOT NULL)
+			OR (kind <> 'notice' AND body_text IS NULL AND body_html IS NULL AND (
+				(kind = 'newsletter'
+					AND newsletter_issue_id IS NOT NULL AND subscriber_id IS NOT NULL)
+				OR (kind IN ('confirmation', 'welcome')
+					AND subscriber_id IS NOT NULL AND list_id IS NOT NULL)
+				OR (kind = 'email_change' AND subscriber_id IS NOT NULL)
+				OR kind = 'redacted'
+			))
+		);
+COMMIT;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- The notice sent to the old address when a subscriber's address changes names the new address,
+-- so it belongs to the subscriber, and has to be exported and erased along with them. Notices
+-- stored until now only carry the old address; they are linked to the subscriber whose current
+-- address they name.
+BEGIN;
+	UPDATE email_dead_letters d
+		SET subscriber_id = s.id
+		FROM subscriptions s
+		WHERE d.kind = 'notice'
+			AND d.subscriber_id IS NULL
+			AND d.subject = 'Your subscription has moved'
+			AND strpos(d.body_text, 'now goes to ' || s.email || '.') > 0;
+COMMIT;
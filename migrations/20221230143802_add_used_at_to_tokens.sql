@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Confirming a subscription marks its token used instead of deleting it, so that a second click
+-- on the link, or a mail scanner fetching it first, is answered without confirming again.
+BEGIN;
+	ALTER TABLE tokens ADD COLUMN used_at timestamptz NULL;
+COMMIT;
@@ -0,0 +1,16 @@
+//! Authentication for the admin area.
+//!
+//! Admin users log in with a username and password. Passwords are stored as Argon2id hashes, and
+//! a successful login is remembered in a cookie-backed session whose state lives in a pluggable
+//! [`SessionStore`](actix_session::storage::SessionStore).
+mod middleware;
+pub use middleware::*;
+
+mod password;
+pub use password::*;
+
+mod session;
+pub use session::*;
+
+mod session_store;
+pub use session_store::*;
@@ -0,0 +1,95 @@
+use super::TypedSession;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::LOCATION;
+use actix_web::{Error, FromRequest, HttpMessage, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// The ID of the logged in user, available as a request extension behind [`RequireLogin`].
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Middleware that only lets requests from logged in users through.
+///
+/// Anonymous requests are redirected to the login form. For everyone else the user's ID is
+/// inserted into the request extensions as a [`UserId`].
+pub struct RequireLogin;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireLogin
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireLoginMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireLoginMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequireLoginMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireLoginMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let session = {
+                let (http_request, payload) = req.parts_mut();
+                TypedSession::from_request(http_request, payload).await?
+            };
+            match session.get_user_id() {
+                Ok(Some(user_id)) => {
+                    req.extensions_mut().insert(UserId(user_id));
+                    let response = service.call(req).await?;
+                    Ok(response.map_into_left_body())
+                }
+                Ok(None) => {
+                    let response = HttpResponse::SeeOther()
+                        .insert_header((LOCATION, "/login"))
+                        .finish();
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+                Err(e) => {
+                    tracing::error!("Failed to read the session: {:?}", e);
+                    let response = HttpResponse::InternalServerError().finish();
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+/// A username and password pair, as submitted to the login form.
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// The reason a set of credentials could not be validated.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The username is unknown or the password is wrong.
+    InvalidCredentials,
+    /// Something went wrong while checking the credentials.
+    Unexpected(anyhow::Error),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidCredentials => write!(f, "Invalid credentials"),
+            Self::Unexpected(e) => write!(f, "Failed to validate credentials: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidCredentials => None,
+            Self::Unexpected(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Check a username and password against the users table, returning the matching user's ID.
+///
+/// When the username is unknown the password is still verified, against a dummy hash, so that
+/// response times don't reveal which usernames exist.
+#[tracing::instrument(name = "Validating credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &sqlx::PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(|e| AuthError::Unexpected(e.into()))?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")
+    .map_err(AuthError::Unexpected)??;
+
+    // Only reached when the password matched, which can't happen with the dummy hash.
+    user_id.ok_or(AuthError::InvalidCredentials)
+}
+
+/// Hash a password with Argon2id, using a fresh random salt.
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// Create a user with the given credentials, returning the new user's ID.
+#[tracing::instrument(name = "Creating user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    pool: &sqlx::PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash)
+        VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the new user.")?;
+    Ok(user_id)
+}
+
+/// Look up the username of a user by ID.
+pub async fn get_username(user_id: Uuid, pool: &sqlx::PgPool) -> Result<String, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.username)
+}
+
+async fn get_stored_credentials(
+    username: &str,
+    pool: &sqlx::PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash FROM users
+        WHERE username = $1
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| (row.user_id, Secret::new(row.password_hash))))
+}
+
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")
+        .map_err(AuthError::Unexpected)?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_argon2id() {
+        let hash = compute_password_hash(Secret::new("hunter2".into())).unwrap();
+        assert!(hash.expose_secret().starts_with("$argon2id$"));
+    }
+
+    #[test]
+    fn matching_password_verifies() {
+        let hash = compute_password_hash(Secret::new("hunter2".into())).unwrap();
+        assert!(verify_password_hash(hash, Secret::new("hunter2".into())).is_ok());
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let hash = compute_password_hash(Secret::new("hunter2".into())).unwrap();
+        assert!(matches!(
+            verify_password_hash(hash, Secret::new("hunter3".into())),
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn same_password_gets_different_salts() {
+        let first = compute_password_hash(Secret::new("hunter2".into())).unwrap();
+        let second = compute_password_hash(Secret::new("hunter2".into())).unwrap();
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+}
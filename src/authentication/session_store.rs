@@ -0,0 +1,362 @@
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type SessionState = HashMap<String, String>;
+/// Session states along with their expiry, by session key hash.
+type SessionMap = HashMap<String, (SessionState, DateTime<Utc>)>;
+
+/// The session store picked in the configuration.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Postgres(PgSessionStore),
+    Memory(InMemorySessionStore),
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Postgres(store) => store.load(session_key).await,
+            Self::Memory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+            Self::Memory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.delete(session_key).await,
+            Self::Memory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// Session store backed by the `sessions` table.
+///
+/// Sessions survive restarts and are shared by every instance of the app. Only a SHA-256 hash of
+/// each session key is stored, so reading the table doesn't give access to anyone's session.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: sqlx::PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Remove every expired session from the table.
+    async fn delete_expired(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE expires_at <= now()
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state FROM sessions
+            WHERE session_key_hash = $1 AND expires_at > now()
+            "#,
+            hash_session_key(session_key)
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| LoadError::Other(e.into()))?;
+        row.map(|row| serde_json::from_str(&row.state))
+            .transpose()
+            .map_err(|e| LoadError::Deserialization(e.into()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| SaveError::Serialization(e.into()))?;
+        if let Err(e) = self.delete_expired().await {
+            tracing::warn!("Failed to delete expired sessions: {:?}", e);
+        }
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key_hash, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            hash_session_key(&session_key),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| SaveError::Other(e.into()))?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| UpdateError::Serialization(e.into()))?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions SET state = $2, expires_at = $3
+            WHERE session_key_hash = $1 AND expires_at > now()
+            "#,
+            hash_session_key(&session_key),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UpdateError::Other(e.into()))?;
+        if result.rows_affected() > 0 {
+            return Ok(session_key);
+        }
+        // The session expired in the meantime, so it is stored again under a new key.
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions SET expires_at = $2
+            WHERE session_key_hash = $1
+            "#,
+            hash_session_key(session_key),
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session TTL.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE session_key_hash = $1
+            "#,
+            hash_session_key(session_key)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
+
+/// Session store kept in process memory.
+///
+/// Sessions are lost on restart and aren't shared between instances, so this is only meant for
+/// development and single-instance deployments. Clones share the same sessions.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<SessionMap>>,
+}
+
+impl InMemorySessionStore {
+    fn lock(&self) -> Result<MutexGuard<'_, SessionMap>, anyhow::Error> {
+        self.sessions
+            .lock()
+            .map_err(|_| anyhow!("The session store lock is poisoned."))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.lock().map_err(LoadError::Other)?;
+        Ok(sessions
+            .get(&hash_session_key(session_key))
+            .filter(|(_, expires_at)| *expires_at > Utc::now())
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let mut sessions = self.lock().map_err(SaveError::Other)?;
+        let now = Utc::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        let session_key = generate_session_key();
+        sessions.insert(
+            hash_session_key(&session_key),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        {
+            let mut sessions = self.lock().map_err(UpdateError::Other)?;
+            if let Some(session) = sessions
+                .get_mut(&hash_session_key(&session_key))
+                .filter(|(_, expires_at)| *expires_at > Utc::now())
+            {
+                *session = (session_state, expires_at(ttl));
+                return Ok(session_key);
+            }
+        }
+        // The session expired in the meantime, so it is stored again under a new key.
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        let mut sessions = self.lock()?;
+        if let Some((_, expires_at_ref)) = sessions.get_mut(&hash_session_key(session_key)) {
+            *expires_at_ref = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.lock()?.remove(&hash_session_key(session_key));
+        Ok(())
+    }
+}
+
+/// Randomly generate a session key.
+fn generate_session_key() -> SessionKey {
+    let key: String = OsRng
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(64)
+        .collect();
+    key.try_into()
+        .expect("64 alphanumeric characters are a valid session key")
+}
+
+/// Compute the hex encoded hash under which a session is stored.
+fn hash_session_key(session_key: &SessionKey) -> String {
+    hex::encode(Sha256::digest(session_key.as_ref().as_bytes()))
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> SessionState {
+        HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+    }
+
+    #[tokio::test]
+    async fn saved_session_can_be_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state()));
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::ZERO).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deleted_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+        store.delete(&key).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn update_of_expired_session_issues_new_key() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::ZERO).await.unwrap();
+        let old_key = key.as_ref().to_string();
+        let new_key = store
+            .update(key, state(), &Duration::minutes(5))
+            .await
+            .unwrap();
+        assert_ne!(old_key, new_key.as_ref());
+        assert_eq!(store.load(&new_key).await.unwrap(), Some(state()));
+    }
+
+    #[test]
+    fn stored_key_is_not_the_session_key() {
+        let key = generate_session_key();
+        assert_ne!(hash_session_key(&key), key.as_ref());
+    }
+}
@@ -0,0 +1,91 @@
+//! Bot protection for the public subscription form.
+//!
+//! Three checks keep automated signups out without a third-party CAPTCHA:
+//!
+//! * a honeypot field, hidden from humans, which must be left empty;
+//! * a signed, time-stamped nonce from `GET /subscriptions/challenge`, which must not be submitted
+//!   too quickly, too late, or more than once;
+//! * optionally, a proof-of-work solution bound to that nonce.
+//!
+//! Submissions failing a check are dropped without telling the client why.
+mod nonce;
+pub use nonce::*;
+
+mod pow;
+pub use pow::*;
+
+use crate::configuration::BotProtectionSettings;
+use chrono::Utc;
+
+/// The bot protection fields of a form submission.
+pub struct FormProtection<'a> {
+    pub honeypot: &'a str,
+    pub nonce: Option<&'a str>,
+    pub proof_of_work: Option<&'a str>,
+}
+
+/// Why a submission was taken for a bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    HoneypotFilled,
+    MissingNonce,
+    InvalidNonce,
+    SubmittedTooFast,
+    ExpiredNonce,
+    ReplayedNonce,
+    MissingProofOfWork,
+    InvalidProofOfWork,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Self::HoneypotFilled => "the honeypot field was filled in",
+            Self::MissingNonce => "the form nonce is missing",
+            Self::InvalidNonce => "the form nonce is malformed or has a bad signature",
+            Self::SubmittedTooFast => "the form was submitted too soon after it was served",
+            Self::ExpiredNonce => "the form nonce has expired",
+            Self::ReplayedNonce => "the form nonce was already used",
+            Self::MissingProofOfWork => "the proof of work is missing",
+            Self::InvalidProofOfWork => "the proof of work is wrong",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Run every check on a submission, returning the first one it fails.
+///
+/// The nonce is only recorded as used once the other checks pass, so a bot can't burn the
+/// nonces of a legitimate user.
+pub async fn screen_submission(
+    form: &FormProtection<'_>,
+    settings: &BotProtectionSettings,
+    pool: &sqlx::PgPool,
+) -> Result<Option<Rejection>, sqlx::Error> {
+    if !form.honeypot.trim().is_empty() {
+        return Ok(Some(Rejection::HoneypotFilled));
+    }
+    let requires_pow = settings.pow_difficulty > 0;
+    let nonce = match form.nonce.filter(|nonce| !nonce.is_empty()) {
+        Some(nonce) => nonce,
+        None if settings.require_nonce || requires_pow => return Ok(Some(Rejection::MissingNonce)),
+        None => return Ok(None),
+    };
+    let expires_at = match verify_nonce(nonce, settings, Utc::now()) {
+        Ok(expires_at) => expires_at,
+        Err(rejection) => return Ok(Some(rejection)),
+    };
+    if requires_pow {
+        match form.proof_of_work {
+            None => return Ok(Some(Rejection::MissingProofOfWork)),
+            Some(solution) if !verify_proof_of_work(nonce, solution, settings.pow_difficulty) => {
+                return Ok(Some(Rejection::InvalidProofOfWork))
+            }
+            Some(_) => {}
+        }
+    }
+    if !claim_nonce(nonce, expires_at, pool).await? {
+        return Ok(Some(Rejection::ReplayedNonce));
+    }
+    Ok(None)
+}
@@ -0,0 +1,156 @@
+use super::Rejection;
+use crate::configuration::BotProtectionSettings;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Issue a form nonce: the issue time and some randomness, signed with the configured secret.
+///
+/// Nonces look like `<unix timestamp>.<random>.<hex signature>`.
+pub fn issue_nonce(settings: &BotProtectionSettings, now: DateTime<Utc>) -> String {
+    let random: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(16)
+        .collect();
+    let payload = format!("{}.{}", now.timestamp(), random);
+    let signature = hex::encode(sign(&payload, settings).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// Check a nonce's signature and age, returning when it expires.
+pub fn verify_nonce(
+    nonce: &str,
+    settings: &BotProtectionSettings,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, Rejection> {
+    let (payload, signature) = nonce.rsplit_once('.').ok_or(Rejection::InvalidNonce)?;
+    let signature = hex::decode(signature).map_err(|_| Rejection::InvalidNonce)?;
+    sign(payload, settings)
+        .verify_slice(&signature)
+        .map_err(|_| Rejection::InvalidNonce)?;
+
+    let issued_at = payload
+        .split_once('.')
+        .and_then(|(timestamp, _)| timestamp.parse().ok())
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .ok_or(Rejection::InvalidNonce)?;
+    if now < issued_at + chrono::Duration::seconds(settings.min_fill_secs as i64) {
+        return Err(Rejection::SubmittedTooFast);
+    }
+    let expires_at = issued_at + chrono::Duration::seconds(settings.max_age_secs as i64);
+    if now >= expires_at {
+        return Err(Rejection::ExpiredNonce);
+    }
+    Ok(expires_at)
+}
+
+fn sign(payload: &str, settings: &BotProtectionSettings) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(settings.nonce_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Record a nonce as used, returning `false` if it already was.
+///
+/// Only a hash of the nonce is stored. Nonces that have expired are purged along the way, since
+/// they would be rejected anyway.
+#[tracing::instrument(name = "Claiming form nonce", skip_all)]
+pub async fn claim_nonce(
+    nonce: &str,
+    expires_at: DateTime<Utc>,
+    pool: &sqlx::PgPool,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM used_form_nonces WHERE expires_at < $1",
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO used_form_nonces (nonce_hash, expires_at)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        hex::encode(Sha256::digest(nonce.as_bytes())),
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn settings() -> BotProtectionSettings {
+        BotProtectionSettings {
+            require_nonce: true,
+            nonce_secret: Secret::new("a-secret-for-testing".into()),
+            min_fill_secs: 3,
+            max_age_secs: 3600,
+            pow_difficulty: 0,
+        }
+    }
+
+    #[test]
+    fn nonces_are_accepted_within_their_time_window() {
+        let now = Utc::now();
+        let nonce = issue_nonce(&settings(), now);
+        let submitted_at = now + chrono::Duration::seconds(10);
+        let expires_at = verify_nonce(&nonce, &settings(), submitted_at).unwrap();
+        assert!(expires_at > submitted_at);
+    }
+
+    #[test]
+    fn quick_and_late_submissions_are_rejected() {
+        let now = Utc::now();
+        let nonce = issue_nonce(&settings(), now);
+        assert_eq!(
+            verify_nonce(&nonce, &settings(), now + chrono::Duration::seconds(1)),
+            Err(Rejection::SubmittedTooFast)
+        );
+        assert_eq!(
+            verify_nonce(&nonce, &settings(), now + chrono::Duration::hours(2)),
+            Err(Rejection::ExpiredNonce)
+        );
+    }
+
+    #[test]
+    fn tampered_nonces_are_rejected() {
+        let now = Utc::now();
+        let nonce = issue_nonce(&settings(), now);
+        let (_, rest) = nonce.split_once('.').unwrap();
+        let backdated = format!("{}.{}", now.timestamp() - 60, rest);
+        let later = now + chrono::Duration::seconds(10);
+        assert_eq!(
+            verify_nonce(&backdated, &settings(), later),
+            Err(Rejection::InvalidNonce)
+        );
+        assert_eq!(
+            verify_nonce("not-a-nonce", &settings(), later),
+            Err(Rejection::InvalidNonce)
+        );
+    }
+
+    #[test]
+    fn nonces_signed_with_another_secret_are_rejected() {
+        let now = Utc::now();
+        let mut other = settings();
+        other.nonce_secret = Secret::new("another-secret".into());
+        let nonce = issue_nonce(&other, now);
+        assert_eq!(
+            verify_nonce(&nonce, &settings(), now + chrono::Duration::seconds(10)),
+            Err(Rejection::InvalidNonce)
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use sha2::{Digest, Sha256};
+
+/// Check a proof-of-work solution for a nonce.
+///
+/// A solution is valid if the SHA-256 hash of `<nonce>:<solution>` starts with at least
+/// `difficulty` zero bits. Checking one takes a single hash, while finding one takes about
+/// `2^difficulty` of them.
+pub fn verify_proof_of_work(nonce: &str, solution: &str, difficulty: u8) -> bool {
+    leading_zero_bits(&Sha256::digest(format!("{}:{}", nonce, solution))) >= u32::from(difficulty)
+}
+
+/// Find a proof-of-work solution for a nonce, as a client would.
+pub fn solve_proof_of_work(nonce: &str, difficulty: u8) -> String {
+    (0u64..)
+        .map(|counter| counter.to_string())
+        .find(|solution| verify_proof_of_work(nonce, solution, difficulty))
+        .expect("A solution exists for any reasonable difficulty")
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solutions_verify() {
+        let solution = solve_proof_of_work("some-nonce", 12);
+        assert!(verify_proof_of_work("some-nonce", &solution, 12));
+        assert!(!verify_proof_of_work("other-nonce", &solution, 12));
+    }
+
+    #[test]
+    fn leading_zero_bits_span_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0x0f, 0xff]), 12);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+    }
+}
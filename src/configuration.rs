@@ -1,4 +1,5 @@
-use crate::domain::ListSubscriberEmail;
+use crate::domain::{EmailDomainPolicy, ListSubscriberEmail};
+use crate::mail::{EmailClient, FileTransport, PostmarkTransport, RetryPolicy, SmtpTransport};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
@@ -10,17 +11,264 @@ pub struct Settings {
     pub app: AppSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub tokens: TokenSettings,
+    pub session: SessionSettings,
+    pub health: HealthSettings,
+    pub telemetry: TelemetrySettings,
+    pub api_docs: ApiDocsSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_domains: EmailDomainSettings,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct EmailDomainSettings {
+    /// Domains accepted even if they are blocked or disposable.
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+    /// Domains whose addresses may not subscribe.
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+    /// Reject addresses from disposable email services.
+    pub block_disposable: bool,
+    /// File of disposable domains, one per line, used instead of the bundled list.
+    pub disposable_list_path: Option<String>,
+}
+
+impl EmailDomainSettings {
+    /// Build the domain policy, reading the disposable domain list if one is configured.
+    pub fn policy(&self) -> std::io::Result<EmailDomainPolicy> {
+        let disposable = match (&self.disposable_list_path, self.block_disposable) {
+            (_, false) => Vec::new(),
+            (Some(path), true) => {
+                EmailDomainPolicy::parse_domain_list(&std::fs::read_to_string(path)?)
+            }
+            (None, true) => EmailDomainPolicy::bundled_disposable_domains(),
+        };
+        Ok(EmailDomainPolicy::new(
+            self.allowlist.clone(),
+            self.blocklist.clone(),
+            disposable,
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct BotProtectionSettings {
+    /// Drop form submissions that don't carry a nonce from `GET /subscriptions/challenge`.
+    pub require_nonce: bool,
+    /// Key used to sign form nonces.
+    pub nonce_secret: Secret<String>,
+    /// Submissions arriving sooner than this after their nonce was issued are dropped.
+    pub min_fill_secs: u64,
+    /// How long a nonce stays valid.
+    pub max_age_secs: u64,
+    /// Leading zero bits required of a proof-of-work solution; `0` disables proof of work.
+    pub pow_difficulty: u8,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct RateLimitSettings {
+    /// Where the token buckets are kept.
+    pub store: RateLimitStoreKind,
+    /// Take the client IP from the first `X-Forwarded-For` entry. Only enable this behind a
+    /// proxy that sets the header, since clients can forge it otherwise.
+    pub trust_forwarded_for: bool,
+    pub per_ip: BucketSettings,
+    pub per_email: BucketSettings,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// The `rate_limit_buckets` table, shared by every instance of the app.
+    Postgres,
+    /// Process memory. Each instance enforces its own limits.
+    Memory,
+}
+
+/// A token bucket: up to `capacity` requests in a burst, then one every `refill_interval_secs`.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct BucketSettings {
+    pub capacity: u32,
+    pub refill_interval_secs: u64,
+}
+
+impl BucketSettings {
+    pub fn refill_interval(&self) -> Duration {
+        Duration::from_secs(self.refill_interval_secs)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ApiDocsSettings {
+    /// Serve Swagger UI at `/api/docs/`. The OpenAPI document itself is always served.
+    pub serve_ui: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct MetricsSettings {
+    /// Token scrapers have to send as `Authorization: Bearer <token>`. `/metrics` isn't served
+    /// at all without one.
+    pub bearer_token: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TelemetrySettings {
+    /// Base URL of an OTLP/HTTP collector, such as `http://localhost:4318`. Traces are only
+    /// exported when this is set.
+    pub otlp_endpoint: Option<String>,
+    pub otlp_timeout_secs: u64,
+}
+
+impl TelemetrySettings {
+    pub fn otlp_timeout(&self) -> Duration {
+        Duration::from_secs(self.otlp_timeout_secs)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct HealthSettings {
+    /// Whether the readiness probe also checks that the email provider is reachable.
+    pub check_email_provider: bool,
+    /// How long each readiness check may take before its dependency is reported down.
+    pub timeout_millis: u64,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_millis)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SessionSettings {
+    /// Where session state is kept.
+    pub store: SessionStoreKind,
+    /// Key used to sign session cookies. Must be at least 64 bytes long.
+    pub secret_key: Secret<String>,
+    pub ttl_secs: i64,
+    /// Only send the session cookie over HTTPS.
+    pub cookie_secure: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    /// The `sessions` table, shared by every instance of the app.
+    Postgres,
+    /// Process memory. Sessions are lost on restart and not shared between instances.
+    Memory,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TokenSettings {
+    pub ttl_secs: i64,
+    /// Key used to hash subscription tokens before they are stored.
+    pub hmac_secret: Secret<String>,
+}
+
+impl TokenSettings {
+    /// How long a freshly issued subscription token remains valid for confirmation.
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.ttl_secs)
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct EmailClientSettings {
+    /// Which transport delivers the emails.
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_string: String,
     pub auth_token: Secret<String>,
     pub timeout_secs: u64,
+    pub retry: RetrySettings,
+    /// Required when `transport` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
+    /// Required when `transport` is `file`.
+    pub file: Option<FileTransportSettings>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    /// Postmark's JSON API, using `base_url` and `auth_token`.
+    Postmark,
+    /// An SMTP relay.
+    Smtp,
+    /// `.eml` files written to a local directory.
+    File,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<Secret<String>>,
+    pub password: Option<Secret<String>>,
+    pub starttls: bool,
+}
+
+impl SmtpSettings {
+    pub fn transport(&self, timeout: Duration) -> SmtpTransport {
+        let credentials = match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+            _ => None,
+        };
+        SmtpTransport::new(&self.host, self.port, credentials, self.starttls, timeout)
+            .expect("Failed to build SMTP transport")
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct FileTransportSettings {
+    pub directory: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub jitter_ms: u64,
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: Duration::from_millis(self.base_delay_ms),
+            jitter: Duration::from_millis(self.jitter_ms),
+        }
+    }
 }
 
 impl EmailClientSettings {
+    /// Build an email client from these settings.
+    pub fn client(&self) -> EmailClient {
+        let sender = self.sender().expect("Failed to parse sender email");
+        let client = match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender,
+                PostmarkTransport::new(
+                    self.base_url.clone(),
+                    self.auth_token.clone(),
+                    self.timeout(),
+                ),
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self.smtp.as_ref().expect("Missing SMTP settings");
+                EmailClient::new(sender, smtp.transport(self.timeout()))
+            }
+            EmailTransportKind::File => {
+                let file = self.file.as_ref().expect("Missing file transport settings");
+                EmailClient::new(sender, FileTransport::new(&file.directory))
+            }
+        };
+        client.with_retry_policy(self.retry.policy())
+    }
     pub fn sender(&self) -> Result<ListSubscriberEmail, String> {
         ListSubscriberEmail::try_from(self.sender_string.clone())
     }
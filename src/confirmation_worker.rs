@@ -0,0 +1,186 @@
+//! Background delivery of confirmation emails for imported subscribers.
+//!
+//! Importing subscribers as pending only enqueues one task per new subscription in the
+//! `confirmation_email_queue` table, since sending thousands of emails within the import would
+//! take far too long. The worker in this module drains that queue the same way the
+//! [issue delivery worker](crate::issue_delivery_worker) drains newsletter issues. Tokens are only
+//! stored hashed, so each one is issued just before its email goes out.
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::mail::{store_dead_letter, DeadLetterKind, DeliveryFailure, EmailClient};
+use crate::routes::{confirmation_message, rotate_token_for_id};
+use crate::startup::AppBaseUrl;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long the worker waits before polling again once the queue is empty.
+const EMPTY_QUEUE_BACKOFF: Duration = Duration::from_secs(10);
+/// How long the worker waits before polling again after failing to talk to the database.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Drain the confirmation queue forever, sleeping whenever there is no work to do.
+pub async fn run_confirmation_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: AppBaseUrl,
+    token_settings: TokenSettings,
+) {
+    loop {
+        match try_execute_confirmation_task(&pool, &email_client, &base_url, &token_settings).await
+        {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(EMPTY_QUEUE_BACKOFF).await,
+            Err(e) => {
+                tracing::error!("Failed to execute confirmation task: {:?}", e);
+                tokio::time::sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Claim a single confirmation task, issue a token for it, and send it.
+///
+/// Tasks whose subscription was confirmed or left since it was queued are dropped without
+/// sending anything. Failures are handled like those of newsletter deliveries: retryable ones put
+/// the task back in the queue with a backoff, and the rest end up in the dead-letter table.
+#[tracing::instrument(
+    name = "Executing confirmation task",
+    skip_all,
+    fields(subscriber_id, list_id)
+)]
+pub async fn try_execute_confirmation_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let (mut txn, task) = match dequeue_task(pool).await? {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    tracing::Span::current()
+        .record("subscriber_id", tracing::field::display(task.subscriber_id))
+        .record("list_id", tracing::field::display(task.list_id));
+
+    if task.status != "pending" {
+        tracing::info!("Skipping a subscription that is already {}", task.status);
+        delete_task(txn, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let recipient = match ListSubscriberEmail::try_from(task.email.clone()) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::warn!("Skipping pending subscriber with invalid details: {}", e);
+            delete_task(txn, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let token =
+        rotate_token_for_id(task.subscriber_id, task.list_id, token_settings, &mut txn).await?;
+    let message = confirmation_message(recipient, &task.list_name, &token, base_url);
+    if let Err(error) = email_client.send_mail(message.clone()).await {
+        let attempts = task.n_retries as u32 + 1;
+        let policy = email_client.get_retry_policy();
+        if error.is_retryable() && policy.should_retry(attempts) {
+            let delay = policy.delay_after(attempts);
+            tracing::warn!(
+                "Confirmation attempt {} failed, retrying in {:?}: {}",
+                attempts,
+                delay,
+                error
+            );
+            reschedule_task(txn, &task, delay).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        tracing::error!(
+            "Giving up on confirmation after {} attempts: {}",
+            attempts,
+            error
+        );
+        let failure = DeliveryFailure { attempts, error };
+        let kind = DeadLetterKind::Confirmation {
+            subscriber_id: task.subscriber_id,
+            list_id: task.list_id,
+        };
+        store_dead_letter(&mut txn, &message, &kind, &failure).await?;
+    }
+
+    delete_task(txn, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    n_retries: i32,
+    email: String,
+    list_name: String,
+    status: String,
+}
+
+/// Lock the next task that is due, returning the transaction holding the lock along with the task.
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, sqlx::Error> {
+    let mut txn = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT q.subscriber_id, q.list_id, q.n_retries, s.email, l.name AS list_name, ls.status
+        FROM confirmation_email_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        JOIN lists l ON l.list_id = q.list_id
+        JOIN list_subscriptions ls
+            ON ls.subscriber_id = q.subscriber_id AND ls.list_id = q.list_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut txn)
+    .await?;
+    Ok(task.map(|t| (txn, t)))
+}
+
+/// Remove a finished task from the queue, releasing its lock.
+async fn delete_task(mut txn: PgTransaction, task: &Task) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_queue
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        task.subscriber_id,
+        task.list_id
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
+
+/// Put a failed task back in the queue, to be picked up again once `delay` has passed.
+async fn reschedule_task(
+    mut txn: PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), sqlx::Error> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        task.subscriber_id,
+        task.list_id,
+        execute_after
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
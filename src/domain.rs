@@ -2,11 +2,15 @@
 //!
 //! This module contains types to validate data used internally to the crate.
 
-/// A struct used to validate subscriber names meet the database requirements.
+mod email_domain_policy;
+mod list_slug;
 mod list_subscriber;
 mod list_subscriber_email;
+/// A struct used to validate subscriber names meet the database requirements.
 mod list_subscriber_name;
 
+pub use email_domain_policy::{DomainRejection, EmailDomainPolicy};
+pub use list_slug::ListSlug;
 pub use list_subscriber::ListSubscriber;
 pub use list_subscriber_email::ListSubscriberEmail;
 pub use list_subscriber_name::ListSubscriberName;
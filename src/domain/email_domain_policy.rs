@@ -0,0 +1,154 @@
+use crate::domain::ListSubscriberEmail;
+use std::collections::HashSet;
+
+/// Disposable email domains shipped with the application.
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Why an address was refused because of its domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainRejection {
+    /// The domain is on the configured blocklist.
+    Blocked,
+    /// The domain belongs to a disposable email service.
+    Disposable,
+}
+
+impl std::fmt::Display for DomainRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blocked => f.write_str("Email addresses from this domain are not accepted."),
+            Self::Disposable => f.write_str("Disposable email addresses are not accepted."),
+        }
+    }
+}
+
+/// Which email domains may subscribe.
+///
+/// Each list also covers the subdomains of its entries. The allowlist holds exceptions: its
+/// domains are accepted even when they are blocked or disposable.
+#[derive(Debug, Clone, Default)]
+pub struct EmailDomainPolicy {
+    allowed: HashSet<String>,
+    blocked: HashSet<String>,
+    disposable: HashSet<String>,
+}
+
+impl EmailDomainPolicy {
+    pub fn new(
+        allowed: impl IntoIterator<Item = String>,
+        blocked: impl IntoIterator<Item = String>,
+        disposable: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            allowed: normalize_domains(allowed),
+            blocked: normalize_domains(blocked),
+            disposable: normalize_domains(disposable),
+        }
+    }
+
+    /// The disposable domains bundled with the application.
+    pub fn bundled_disposable_domains() -> Vec<String> {
+        Self::parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS)
+    }
+
+    /// Parse a list of domains, one per line. Blank lines and `#` comments are skipped.
+    pub fn parse_domain_list(contents: &str) -> Vec<String> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    pub fn check(&self, email: &ListSubscriberEmail) -> Result<(), DomainRejection> {
+        let domain = email.domain();
+        if matches(&self.allowed, domain) {
+            Ok(())
+        } else if matches(&self.blocked, domain) {
+            Err(DomainRejection::Blocked)
+        } else if matches(&self.disposable, domain) {
+            Err(DomainRejection::Disposable)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Bring configured domains to the form addresses are normalised to, so that they compare equal.
+fn normalize_domains(domains: impl IntoIterator<Item = String>) -> HashSet<String> {
+    domains
+        .into_iter()
+        .map(|domain| {
+            let domain = domain.trim().trim_end_matches('.');
+            idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase())
+        })
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// Whether `domain`, or any domain it is a subdomain of, is in the set.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = Some(domain);
+    while let Some(current) = candidate {
+        if domains.contains(current) {
+            return true;
+        }
+        candidate = current.split_once('.').map(|(_, parent)| parent);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(address: &str) -> ListSubscriberEmail {
+        ListSubscriberEmail::try_from(address.to_string()).unwrap()
+    }
+
+    fn policy(allowed: &[&str], blocked: &[&str]) -> EmailDomainPolicy {
+        EmailDomainPolicy::new(
+            allowed.iter().map(|d| d.to_string()),
+            blocked.iter().map(|d| d.to_string()),
+            EmailDomainPolicy::bundled_disposable_domains(),
+        )
+    }
+
+    #[test]
+    fn bundled_disposable_domains_are_rejected() {
+        let policy = policy(&[], &[]);
+        assert_eq!(
+            policy.check(&email("someone@Mailinator.com")),
+            Err(DomainRejection::Disposable)
+        );
+        assert_eq!(policy.check(&email("someone@example.com")), Ok(()));
+    }
+
+    #[test]
+    fn subdomains_are_matched() {
+        let policy = policy(&[], &["blocked.example"]);
+        assert_eq!(
+            policy.check(&email("someone@mail.blocked.example")),
+            Err(DomainRejection::Blocked)
+        );
+        assert_eq!(policy.check(&email("someone@notblocked.example")), Ok(()));
+    }
+
+    #[test]
+    fn allowlisted_domains_are_exceptions() {
+        let policy = policy(&["yopmail.com", "Partner.Example"], &["example"]);
+        assert_eq!(policy.check(&email("someone@yopmail.com")), Ok(()));
+        assert_eq!(policy.check(&email("someone@partner.example")), Ok(()));
+        assert_eq!(
+            policy.check(&email("someone@other.example")),
+            Err(DomainRejection::Blocked)
+        );
+    }
+
+    #[test]
+    fn domain_lists_skip_comments_and_blank_lines() {
+        let domains = EmailDomainPolicy::parse_domain_list("# comment\n\n a.example \nb.example\n");
+        assert_eq!(domains, vec!["a.example", "b.example"]);
+    }
+}
@@ -0,0 +1,59 @@
+/// The slug naming a mailing list in URLs and request bodies, such as `weekly-digest`.
+///
+/// Slugs are made of lowercase ASCII letters, digits and inner hyphens, and are at most 64
+/// characters long.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for ListSlug {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let has_valid_chars = s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        let is_valid = !s.is_empty()
+            && s.len() <= 64
+            && has_valid_chars
+            && !s.starts_with('-')
+            && !s.ends_with('-');
+        if is_valid {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid list slug.", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_are_accepted() {
+        for slug in ["default", "weekly-digest", "2023"] {
+            assert!(ListSlug::try_from(slug.to_string()).is_ok(), "{}", slug);
+        }
+    }
+
+    #[test]
+    fn malformed_slugs_are_rejected() {
+        let too_long = "a".repeat(65);
+        for slug in [
+            "",
+            "Weekly",
+            "weekly digest",
+            "-weekly",
+            "weekly-",
+            "wéekly",
+            &too_long,
+        ] {
+            assert!(ListSlug::try_from(slug.to_string()).is_err(), "{}", slug);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::domain::list_subscriber_email::ListSubscriberEmail;
 use crate::domain::list_subscriber_name::ListSubscriberName;
+use crate::error::FieldErrors;
 
 #[derive(Clone, Debug)]
 pub struct ListSubscriber {
@@ -13,4 +14,38 @@ impl ListSubscriber {
         let email = ListSubscriberEmail::try_from(email)?;
         Ok(Self { name, email })
     }
+
+    /// Validate both fields, collecting an error for each invalid one rather than stopping at
+    /// the first.
+    pub fn parse(name: String, email: String) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::default();
+        let name = ListSubscriberName::try_from(name)
+            .map_err(|e| errors.add("name", e))
+            .ok();
+        let email = ListSubscriberEmail::try_from(email)
+            .map_err(|e| errors.add("email", e))
+            .ok();
+        match (name, email) {
+            (Some(name), Some(email)) => Ok(Self { name, email }),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reports_every_invalid_field() {
+        let errors = ListSubscriber::parse("".into(), "not-an-email".into()).unwrap_err();
+        assert!(errors.get("name").is_some());
+        assert!(errors.get("email").is_some());
+    }
+
+    #[test]
+    fn parse_accepts_valid_fields() {
+        let subscriber = ListSubscriber::parse("Tommy".into(), "tommy@example.com".into()).unwrap();
+        assert_eq!(subscriber.email.as_ref(), "tommy@example.com");
+    }
 }
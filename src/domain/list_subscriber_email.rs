@@ -1,19 +1,47 @@
+/// A validated, normalised email address.
+///
+/// Surrounding whitespace is trimmed and the domain is lowercased and IDNA-encoded, while the
+/// local part keeps the case it was typed in; that form is used to send email. Addresses are
+/// compared through their [canonical](Self::canonical) form, which is lowercased throughout.
 #[derive(Debug, Clone)]
-pub struct ListSubscriberEmail(String);
+pub struct ListSubscriberEmail {
+    address: String,
+    canonical: String,
+}
+
+impl ListSubscriberEmail {
+    /// The form used to tell addresses apart, as stored in `subscriptions.email_canonical`.
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// The normalised domain of the address.
+    pub fn domain(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default()
+    }
+}
 
 impl AsRef<str> for ListSubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
 impl TryFrom<String> for ListSubscriberEmail {
     type Error = String;
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        if validator::validate_email(&s) {
-            Ok(Self(s))
+        let invalid = || format!("Email {} failed validation.", s);
+        let (local, domain) = s.trim().rsplit_once('@').ok_or_else(invalid)?;
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let address = format!("{}@{}", local, domain);
+        if validator::validate_email(&address) {
+            let canonical = address.to_lowercase();
+            Ok(Self { address, canonical })
         } else {
-            Err(format!("Email {} failed validation.", s))
+            Err(invalid())
         }
     }
 }
@@ -41,6 +69,17 @@ mod tests {
         assert!(user.is_err());
     }
     #[test]
+    fn whitespace_is_trimmed_and_the_domain_lowercased() {
+        let user = ListSubscriberEmail::try_from(" Foo.Bar@Example.COM\n".to_string()).unwrap();
+        assert_eq!(user.as_ref(), "Foo.Bar@example.com");
+        assert_eq!(user.canonical(), "foo.bar@example.com");
+    }
+    #[test]
+    fn international_domains_are_idna_encoded() {
+        let user = ListSubscriberEmail::try_from("user@Bücher.example".to_string()).unwrap();
+        assert_eq!(user.as_ref(), "user@xn--bcher-kva.example");
+    }
+    #[test]
     fn blank() {
         let email: String = "".into();
         let user = ListSubscriberEmail::try_from(email.clone());
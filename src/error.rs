@@ -0,0 +1,294 @@
+//! The error type returned by request handlers.
+//!
+//! Every [`AppError`] becomes a JSON response of the form
+//! `{"code": "...", "message": "...", "request_id": "..."}`. The message is the error's
+//! `Display` output, which never includes the underlying cause; the full cause chain is in the
+//! `Debug` output, which `TracingLogger` logs once when the response goes out.
+use crate::domain::DomainRejection;
+use crate::mail::DeliveryFailure;
+use actix_web::body::{BoxBody, EitherBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Duration;
+use tracing_actix_web::RequestId;
+use utoipa::ToSchema;
+
+#[derive(thiserror::Error)]
+pub enum AppError {
+    /// The request itself is malformed or fails validation.
+    #[error("{0}")]
+    Validation(String),
+    /// One or more fields of the request are invalid.
+    #[error("The request has invalid fields.")]
+    InvalidFields(FieldErrors),
+    /// The email address is valid, but its domain may not subscribe.
+    #[error("{0}")]
+    EmailDomainRejected(DomainRejection),
+    /// The request is well formed, but can't be applied to the resource in its current state.
+    #[error("{0}")]
+    Unprocessable(String),
+    /// The request clashes with an existing resource.
+    #[error("{0}")]
+    Conflict(String),
+    #[error("The token is not valid.")]
+    InvalidToken,
+    #[error("The token has expired. Please request a new one.")]
+    ExpiredToken,
+    #[error("Too many requests. Please try again later.")]
+    RateLimited(Duration),
+    #[error("The requested {0} does not exist.")]
+    NotFound(&'static str),
+    #[error("The email could not be delivered.")]
+    Email(#[source] DeliveryFailure),
+    #[error("The database could not be reached.")]
+    Database(#[from] sqlx::Error),
+    #[error("Something went wrong.")]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl AppError {
+    /// A stable, machine-readable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(_) | Self::InvalidFields(_) => "validation_error",
+            Self::EmailDomainRejected(_) => "email_domain_rejected",
+            Self::Unprocessable(_) => "unprocessable",
+            Self::Conflict(_) => "conflict",
+            Self::InvalidToken => "invalid_token",
+            Self::ExpiredToken => "expired_token",
+            Self::RateLimited(_) => "rate_limited",
+            Self::NotFound(_) => "not_found",
+            Self::Email(_) => "email_delivery_failed",
+            Self::Database(_) => "database_error",
+            Self::Unexpected(_) => "internal_error",
+        }
+    }
+
+    fn body(&self, request_id: Option<RequestId>) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            request_id: request_id.map(|id| id.to_string()),
+            fields: match self {
+                Self::InvalidFields(fields) => Some(fields.clone()),
+                Self::EmailDomainRejected(rejection) => {
+                    let mut fields = FieldErrors::default();
+                    fields.add("email", rejection.to_string());
+                    Some(fields)
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Debug for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::InvalidFields(_) | Self::EmailDomainRejected(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Email(_) => StatusCode::BAD_GATEWAY,
+            Self::Database(_) | Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        // The request ID is filled in by `ErrorRequestId`, which can see the request.
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::RateLimited(retry_after) = self {
+            // Retry-After only takes whole seconds; round up so that retrying on time succeeds.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.insert_header((RETRY_AFTER, seconds.max(1)));
+        }
+        response.json(self.body(None))
+    }
+}
+
+/// The body of every [`AppError`] response.
+#[derive(serde::Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Machine-readable kind of error, such as `validation_error`.
+    code: &'static str,
+    /// Human-readable description, safe to show to users.
+    message: String,
+    /// ID of the request in the server logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// Errors for individual fields, keyed by field name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<FieldErrors>,
+}
+
+/// Validation errors keyed by the name of the offending field.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, ToSchema)]
+#[schema(value_type = BTreeMap<String, String>)]
+pub struct FieldErrors(BTreeMap<&'static str, String>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, error: String) {
+        self.0.insert(field, error);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(field).map(String::as_str)
+    }
+}
+
+/// Error handler for the body, query and path extractors, so that malformed requests get the
+/// same JSON error body as the rest of the API.
+pub fn validation_error(e: impl std::fmt::Display, _: &HttpRequest) -> Error {
+    AppError::Validation(e.to_string()).into()
+}
+
+/// Write an error followed by each of its causes, one per line.
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+/// Middleware adding the request ID assigned by `TracingLogger` to [`AppError`] response bodies,
+/// so that a client reporting an error can point at the matching logs.
+///
+/// It has to sit inside `TracingLogger`, which generates the ID.
+pub struct ErrorRequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ErrorRequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorRequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ErrorRequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let response = service.call(req).await?;
+            let request_id = response.request().extensions().get::<RequestId>().copied();
+            let body = response
+                .response()
+                .error()
+                .and_then(|e| e.as_error::<AppError>())
+                .map(|e| e.body(request_id));
+            Ok(match body.map(|body| serde_json::to_string(&body)) {
+                // The response keeps the original error attached, for `TracingLogger` to log.
+                Some(Ok(body)) => response.map_body(|_, _| EitherBody::right(BoxBody::new(body))),
+                _ => response.map_into_left_body(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::SendError;
+
+    #[test]
+    fn debug_output_includes_the_cause() {
+        let error = AppError::Email(DeliveryFailure {
+            attempts: 3,
+            error: SendError::Retryable("connection reset".into()),
+        });
+        let debug = format!("{:?}", error);
+        assert!(debug.contains("The email could not be delivered."));
+        assert!(debug.contains("connection reset"), "{}", debug);
+    }
+
+    #[test]
+    fn message_does_not_leak_the_cause() {
+        let error = AppError::Database(sqlx::Error::PoolTimedOut);
+        assert_eq!(error.to_string(), "The database could not be reached.");
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_web::test]
+    async fn error_response_is_json() {
+        let response = AppError::Validation("Bad name.".into()).error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "validation_error");
+        assert_eq!(body["message"], "Bad name.");
+        assert!(body.get("fields").is_none());
+    }
+
+    #[test]
+    fn rate_limited_responses_say_when_to_retry() {
+        let response = AppError::RateLimited(Duration::from_millis(1500)).error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "2");
+    }
+
+    #[actix_web::test]
+    async fn field_errors_are_listed_in_the_body() {
+        let mut fields = FieldErrors::default();
+        fields.add("email", "Bad email.".into());
+        let response = AppError::InvalidFields(fields).error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "validation_error");
+        assert_eq!(body["fields"]["email"], "Bad email.");
+    }
+}
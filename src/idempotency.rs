@@ -0,0 +1,16 @@
+//! Idempotency keys for requests that must not be processed twice.
+//!
+//! A client may send an `Idempotency-Key` header with a publish-style request. The first request
+//! with a given key is processed normally and its response is saved, per user, in the
+//! `idempotency` table. Retries with the same key get the saved response back instead of being
+//! processed again.
+//!
+//! Claiming a key inserts its row inside the transaction that processes the request. A duplicate
+//! request arriving in the meantime blocks on that row until the transaction ends, then either
+//! finds the saved response or, if the first request failed and rolled back, claims the key
+//! itself.
+mod key;
+pub use key::*;
+
+mod persistence;
+pub use persistence::*;
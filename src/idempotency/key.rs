@@ -0,0 +1,73 @@
+use actix_web::HttpRequest;
+
+/// Name of the header carrying the idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// A validated idempotency key, as chosen by the client.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let max_length = 64;
+        if s.is_empty() || s.len() > max_length {
+            Err(format!(
+                "The idempotency key must be between 1 and {} characters long.",
+                max_length
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+/// Read the idempotency key from a request, if it has one.
+pub fn get_idempotency_key(request: &HttpRequest) -> Result<Option<IdempotencyKey>, String> {
+    match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => {
+            let key = value
+                .to_str()
+                .map_err(|_| "The idempotency key must be printable ASCII.".to_string())?;
+            IdempotencyKey::try_from(key.to_string()).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn empty_key_is_rejected() {
+        assert!(IdempotencyKey::try_from(String::new()).is_err());
+    }
+
+    #[test]
+    fn long_key_is_rejected() {
+        assert!(IdempotencyKey::try_from("a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn key_is_read_from_header() {
+        let request = TestRequest::default()
+            .insert_header((IDEMPOTENCY_KEY_HEADER, "publish-42"))
+            .to_http_request();
+        let key = get_idempotency_key(&request).unwrap().unwrap();
+        assert_eq!(key.as_ref(), "publish-42");
+    }
+
+    #[test]
+    fn missing_header_is_not_an_error() {
+        let request = TestRequest::default().to_http_request();
+        assert!(get_idempotency_key(&request).unwrap().is_none());
+    }
+}
@@ -0,0 +1,138 @@
+use super::IdempotencyKey;
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+/// What to do with a request carrying an idempotency key.
+pub enum NextAction {
+    /// The key is new: process the request inside this transaction, then hand it to
+    /// [`save_response`].
+    StartProcessing(Box<PgTransaction>),
+    /// The key was used before: answer with the saved response.
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claim an idempotency key for a user, or fetch the response saved for it.
+///
+/// Waits for any other request holding the same key to finish first.
+#[tracing::instrument(name = "Claiming idempotency key", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut txn = pool.begin().await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut txn)
+    .await?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        return Ok(NextAction::StartProcessing(Box::new(txn)));
+    }
+    let saved_response = get_saved_response(pool, idempotency_key, user_id)
+        .await?
+        .context("A processed idempotency key has no saved response.")?;
+    Ok(NextAction::ReturnSavedResponse(saved_response))
+}
+
+/// Save the response to a request alongside its idempotency key, then commit the transaction
+/// that processed it. Returns an equivalent response to send back to the client.
+#[tracing::instrument(name = "Saving idempotent response", skip(txn, http_response))]
+pub async fn save_response(
+    mut txn: PgTransaction,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read the response body: {}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+    // The array of composite values can't be checked at compile time.
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET response_status_code = $3, response_headers = $4, response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code AS "response_status_code!",
+            response_headers AS "response_headers!: Vec<HeaderPairRecord>",
+            response_body AS "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2 AND response_status_code IS NOT NULL
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+    let saved_response = match saved_response {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let status_code = StatusCode::from_u16(saved_response.response_status_code.try_into()?)?;
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in saved_response.response_headers {
+        response.append_header((name, value));
+    }
+    Ok(Some(response.body(saved_response.response_body)))
+}
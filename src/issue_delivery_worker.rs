@@ -0,0 +1,254 @@
+//! Background delivery of newsletter issues.
+//!
+//! Publishing an issue only enqueues one task per confirmed subscriber in the
+//! `issue_delivery_queue` table. The worker in this module drains that queue, so delivery is
+//! decoupled from the publishing request. Tasks are claimed with `FOR UPDATE SKIP LOCKED`, which
+//! lets any number of replicas drain the same queue without sending an issue twice.
+//!
+//! Every issue goes out with a signed link that unsubscribes its recipient from the issue's list,
+//! both in the footer and as a one-click `List-Unsubscribe` header. The footer also links to the
+//! recipient's preference page.
+//!
+//! Failed sends are retried with the email client's retry policy. Tasks that can't be delivered
+//! end up in the dead-letter table, where they can be inspected and replayed.
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::mail::{store_dead_letter, DeadLetterKind, DeliveryFailure, EmailClient, EmailMessage};
+use crate::routes::{management_link, unsubscribe_link};
+use crate::startup::AppBaseUrl;
+use chrono::Utc;
+use sqlx::{PgConnection, PgExecutor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long the worker waits before polling again once the queue is empty.
+const EMPTY_QUEUE_BACKOFF: Duration = Duration::from_secs(10);
+/// How long the worker waits before polling again after failing to talk to the database.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The result of a single attempt to take work from the queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Drain the delivery queue forever, sleeping whenever there is no work to do.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: AppBaseUrl,
+    token_settings: TokenSettings,
+) {
+    loop {
+        match try_execute_task(&pool, &email_client, &base_url, &token_settings).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(EMPTY_QUEUE_BACKOFF).await,
+            Err(e) => {
+                tracing::error!("Failed to execute delivery task: {:?}", e);
+                tokio::time::sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Claim a single delivery task, send it, and remove it from the queue.
+///
+/// The task stays locked by this transaction until it commits, so other workers skip over it
+/// instead of blocking on it or sending it again. Retryable send failures put the task back in
+/// the queue with an exponential backoff; permanent failures, and tasks out of retries, are moved
+/// to the dead-letter table.
+#[tracing::instrument(
+    name = "Executing delivery task",
+    skip_all,
+    fields(newsletter_issue_id, subscriber_email)
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let (mut txn, task) = match dequeue_task(pool).await? {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(task.newsletter_issue_id),
+        )
+        .record(
+            "subscriber_email",
+            tracing::field::display(&task.subscriber_email),
+        );
+
+    match ListSubscriberEmail::try_from(task.subscriber_email.clone()) {
+        Ok(recipient) => {
+            let message = render_issue(
+                &mut txn,
+                task.newsletter_issue_id,
+                task.subscriber_id,
+                recipient,
+                base_url,
+                token_settings,
+            )
+            .await?;
+            if let Err(error) = email_client.send_mail(message.clone()).await {
+                let attempts = task.n_retries as u32 + 1;
+                let policy = email_client.get_retry_policy();
+                if error.is_retryable() && policy.should_retry(attempts) {
+                    let delay = policy.delay_after(attempts);
+                    tracing::warn!(
+                        "Delivery attempt {} failed, retrying in {:?}: {}",
+                        attempts,
+                        delay,
+                        error
+                    );
+                    reschedule_task(txn, &task, delay).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                tracing::error!(
+                    "Giving up on delivery after {} attempts: {}",
+                    attempts,
+                    error
+                );
+                let failure = DeliveryFailure { attempts, error };
+                let kind = DeadLetterKind::Newsletter {
+                    newsletter_issue_id: task.newsletter_issue_id,
+                    subscriber_id: task.subscriber_id,
+                };
+                store_dead_letter(&mut txn, &message, &kind, &failure).await?;
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Skipping confirmed subscriber with invalid details: {}", e);
+        }
+    }
+
+    delete_task(txn, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    subscriber_id: Uuid,
+    n_retries: i32,
+}
+
+/// Lock the next task that is due, returning the transaction holding the lock along with the task.
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, sqlx::Error> {
+    let mut txn = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, subscriber_id, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut txn)
+    .await?;
+    Ok(task.map(|t| (txn, t)))
+}
+
+/// Remove a finished task from the queue, releasing its lock.
+async fn delete_task(mut txn: PgTransaction, task: &Task) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
+
+/// Put a failed task back in the queue, to be picked up again once `delay` has passed.
+async fn reschedule_task(
+    mut txn: PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), sqlx::Error> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    body_text: String,
+    body_html: String,
+}
+
+async fn get_issue<'e, E>(executor: E, issue_id: Uuid) -> Result<NewsletterIssue, sqlx::Error>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT list_id, title, body_text, body_html
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Render an issue for one subscriber, with their unsubscribe link in the footer and headers,
+/// and the link to their preference page in the footer.
+///
+/// This is shared with the replay of dead letters.
+pub(crate) async fn render_issue(
+    connection: &mut PgConnection,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    recipient: ListSubscriberEmail,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+) -> Result<EmailMessage, sqlx::Error> {
+    let issue = get_issue(&mut *connection, newsletter_issue_id).await?;
+    let manage_link = management_link(subscriber_id, token_settings, base_url, connection).await?;
+    let unsubscribe_link = unsubscribe_link(subscriber_id, issue.list_id, token_settings, base_url);
+    Ok(EmailMessage {
+        recipient,
+        subject: issue.title,
+        body_text: format!(
+            "{}\n\nManage your subscription: {}\nUnsubscribe from this list: {}",
+            issue.body_text, manage_link, unsubscribe_link
+        ),
+        body_html: format!(
+            "{}<p><a href=\"{}\">Manage your subscription</a> | \
+            <a href=\"{}\">Unsubscribe from this list</a></p>",
+            issue.body_html,
+            htmlescape::encode_minimal(&manage_link),
+            htmlescape::encode_minimal(&unsubscribe_link)
+        ),
+        list_unsubscribe: Some(unsubscribe_link),
+    })
+}
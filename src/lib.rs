@@ -2,9 +2,21 @@
 //!
 //! This codebase implements the REST API generated by working through the book Zero to Production,
 //! by Luca Palmieri.
+pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
+pub mod confirmation_worker;
 pub mod domain;
+pub mod error;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod lists;
 pub mod mail;
+pub mod metrics;
+pub mod personal_data;
+pub mod rate_limit;
 pub mod routes;
 pub mod startup;
+pub mod subscriber_export;
+pub mod subscriber_import;
 pub mod telemetry;
@@ -0,0 +1,77 @@
+//! Mailing lists.
+//!
+//! Every subscriber address is stored once in `subscriptions`, and joins lists through
+//! `list_subscriptions`, which holds the status of each subscription. Confirmation and
+//! unsubscribe tokens belong to a single list.
+use crate::domain::ListSlug;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// The slug of the list that subscriptions go to when no list is given. Subscriptions made before
+/// lists existed were moved to it.
+pub const DEFAULT_LIST_SLUG: &str = "default";
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Find a list by its slug.
+pub async fn get_list_by_slug(
+    slug: &str,
+    pool: &sqlx::PgPool,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name, created_at FROM lists
+        WHERE slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Every list, oldest first.
+pub async fn all_lists(pool: &sqlx::PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name, created_at FROM lists
+        ORDER BY created_at, slug
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Create a list. Slugs are unique, so this fails with a unique violation if the slug is taken.
+pub async fn insert_list(
+    slug: &ListSlug,
+    name: &str,
+    pool: &sqlx::PgPool,
+) -> Result<MailingList, sqlx::Error> {
+    let list = MailingList {
+        list_id: Uuid::new_v4(),
+        slug: slug.as_ref().to_owned(),
+        name: name.to_owned(),
+        created_at: Utc::now(),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        list.list_id,
+        list.slug,
+        list.name,
+        list.created_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(list)
+}
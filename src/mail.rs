@@ -1,10 +1,18 @@
 //! Components related to sending e-mail traffic.
 use crate::domain::ListSubscriberEmail;
-use reqwest::{Client, Response};
-use secrecy::{ExposeSecret, Secret};
-use std::time::Duration;
+use crate::metrics::record_email_send;
+
+mod dead_letter;
+pub use dead_letter::*;
+
+mod retry;
+pub use retry::*;
+
+mod transport;
+pub use transport::*;
 
 /// Represents an e-mail message to be sent by an EmailClient.
+#[derive(Clone, Debug)]
 pub struct EmailMessage {
     /// The recipient of the email.
     pub recipient: ListSubscriberEmail,
@@ -14,31 +22,25 @@ pub struct EmailMessage {
     pub body_text: String,
     /// The HTML representation of the email body
     pub body_html: String,
+    /// A one-click unsubscribe URL, sent as the `List-Unsubscribe` header along with
+    /// `List-Unsubscribe-Post` (RFC 8058). Only list mail carries one.
+    pub list_unsubscribe: Option<String>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct EmailApiRequest {
-    from: String,
-    to: String,
-    subject: String,
-    text_body: String,
-    html_body: String,
-}
 /// An Email Client
 ///
-/// This system is responsible for handling sending out email messages.
+/// This system is responsible for handling sending out email messages. The actual delivery is
+/// left to an `EmailTransport`, so the same client can talk to Postmark, an SMTP relay, or a
+/// directory of `.eml` files.
 ///
 #[derive(Debug)]
 pub struct EmailClient {
     /// The email used as the "From" address on emails being sent.
     sender: ListSubscriberEmail,
-    /// The HTTP client used to process the REST API requests to send emails.
-    http_client: Client,
-    /// The base API url for the mail service used to send emails.
-    api_url: String,
-    /// The API token used to authenticate with the mail application
-    auth_token: Secret<String>,
+    /// The transport that delivers the emails.
+    transport: Box<dyn EmailTransport>,
+    /// How failed sends are retried.
+    retry_policy: RetryPolicy,
 }
 
 impl EmailClient {
@@ -47,22 +49,23 @@ impl EmailClient {
     /// # Arguments
     ///
     /// * `sender` - a ListSubscriberEmail object representing the sender's address
-    /// * `api_url` - a String representing the base API url used to send emails
-    /// * `auth_token` - an API token for the mail REST interface.
-    /// * `timeout` - Request timeout.
-    pub fn new(
-        sender: ListSubscriberEmail,
-        api_url: String,
-        auth_token: Secret<String>,
-        timeout: Duration,
-    ) -> Self {
+    /// * `transport` - the EmailTransport used to deliver emails.
+    pub fn new(sender: ListSubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
         Self {
             sender,
-            http_client: Client::builder().timeout(timeout).build().unwrap(),
-            api_url,
-            auth_token,
+            transport: Box::new(transport),
+            retry_policy: RetryPolicy::default(),
         }
     }
+    /// Replace the default retry policy used by `send_mail_with_retry`.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// Expose the retry policy, for callers that schedule their own retries.
+    pub fn get_retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
     /// Expose the sender email address.
     pub fn get_sender(&self) -> &ListSubscriberEmail {
         &self.sender
@@ -72,37 +75,64 @@ impl EmailClient {
     /// # Arguments
     ///
     /// * `message`: an EmailMessage representing the email to be sent.
-    pub async fn send_mail(&self, message: EmailMessage) -> Result<Response, reqwest::Error> {
-        let client = &self.http_client;
-        let url = format!("{}/email", self.api_url);
-        let body = EmailApiRequest {
-            to: message.recipient.as_ref().to_owned(),
-            from: self.sender.as_ref().to_owned(),
-            subject: message.subject,
-            html_body: message.body_html,
-            text_body: message.body_text,
-        };
-        client
-            .post(url)
-            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()
+    pub async fn send_mail(&self, message: EmailMessage) -> Result<(), SendError> {
+        let outcome = self.transport.send(&self.sender, &message).await;
+        match &outcome {
+            Ok(()) => record_email_send(self.transport.name(), "sent", "ok"),
+            Err(e) => record_email_send(
+                self.transport.name(),
+                if e.is_retryable() {
+                    "retryable"
+                } else {
+                    "permanent"
+                },
+                e.provider_status().as_deref().unwrap_or("none"),
+            ),
+        }
+        outcome
+    }
+    /// Check that the transport's mail service can be reached, without sending anything.
+    pub async fn check_reachable(&self) -> Result<(), SendError> {
+        self.transport.check_reachable().await
+    }
+    /// Send out the given email, retrying transient failures in place according to the client's
+    /// retry policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `message`: an EmailMessage representing the email to be sent.
+    pub async fn send_mail_with_retry(&self, message: EmailMessage) -> Result<(), DeliveryFailure> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.send_mail(message.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(error) if error.is_retryable() && self.retry_policy.should_retry(attempts) => {
+                    let delay = self.retry_policy.delay_after(attempts);
+                    tracing::warn!(
+                        "Send attempt {} failed, retrying in {:?}: {}",
+                        attempts,
+                        delay,
+                        error
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(error) => return Err(DeliveryFailure { attempts, error }),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::EmailApiRequest;
     use crate::domain::ListSubscriberEmail;
-    use crate::mail::{EmailClient, EmailMessage};
+    use crate::mail::{EmailClient, EmailMessage, PostmarkTransport, RetryPolicy};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::Fake;
     use secrecy::Secret;
     use std::time::Duration;
-    use wiremock::matchers::{any, body_json_schema, header, header_exists, method, path};
+    use wiremock::matchers::any;
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     fn arrange_message() -> EmailMessage {
@@ -112,6 +142,7 @@ mod tests {
             subject: Sentence(1..3).fake(),
             body_text: message_body.clone(),
             body_html: message_body,
+            list_unsubscribe: None,
         }
     }
 
@@ -123,45 +154,38 @@ mod tests {
         ListSubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap()
     }
 
+    fn postmark_client(api_url: String, timeout: Duration) -> EmailClient {
+        EmailClient::new(
+            get_sender(),
+            PostmarkTransport::new(api_url, get_token(), timeout),
+        )
+    }
+
     #[tokio::test]
-    async fn send_mail_delivers_correct_request() {
+    async fn send_mail_returns_error_on_http_error() {
         // Arrange
         let mock_server = MockServer::start().await;
-        let email_client = EmailClient::new(
-            get_sender(),
-            mock_server.uri(),
-            get_token(),
-            Duration::from_secs(5),
-        );
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5));
 
-        Mock::given(body_json_schema::<EmailApiRequest>)
-            .and(path("/email"))
-            .and(method("POST"))
-            .and(header_exists("X-Postmark-Server-Token"))
-            .and(header("Content-Type", "application/json"))
-            .respond_with(ResponseTemplate::new(200))
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
             .expect(1)
             .mount(&mock_server)
             .await;
 
         // Act
         let send_result = email_client.send_mail(arrange_message()).await;
-        assert!(send_result.is_ok());
+        assert!(send_result.is_err(), "Result was: {:?}", send_result);
     }
 
     #[tokio::test]
-    async fn send_mail_returns_error_on_http_error() {
+    async fn send_mail_returns_error_on_timeout() {
         // Arrange
         let mock_server = MockServer::start().await;
-        let email_client = EmailClient::new(
-            get_sender(),
-            mock_server.uri(),
-            get_token(),
-            Duration::from_secs(5),
-        );
+        let email_client = postmark_client(mock_server.uri(), Duration::from_millis(50));
 
         Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
+            .respond_with(ResponseTemplate::new(500).set_delay(std::time::Duration::from_secs(180)))
             .expect(1)
             .mount(&mock_server)
             .await;
@@ -171,25 +195,171 @@ mod tests {
         assert!(send_result.is_err(), "Result was: {:?}", send_result);
     }
 
+    fn fast_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            jitter: Duration::from_millis(0),
+        }
+    }
+
     #[tokio::test]
-    async fn send_mail_returns_error_on_timeout() {
+    async fn server_errors_and_rate_limits_are_retryable() {
+        for status in [500, 503, 429] {
+            // Arrange
+            let mock_server = MockServer::start().await;
+            let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5));
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&mock_server)
+                .await;
+
+            // Act
+            let send_result = email_client.send_mail(arrange_message()).await;
+
+            // Assert
+            let error = send_result.unwrap_err();
+            assert!(error.is_retryable(), "{} was not retryable", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_errors_carry_the_provider_status() {
         // Arrange
         let mock_server = MockServer::start().await;
-        let email_client = EmailClient::new(
-            get_sender(),
-            mock_server.uri(),
-            get_token(),
-            Duration::from_millis(50),
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client.send_mail(arrange_message()).await;
+
+        // Assert
+        assert_eq!(
+            send_result.unwrap_err().provider_status().as_deref(),
+            Some("422")
         );
+    }
 
+    #[tokio::test]
+    async fn timeouts_have_no_provider_status() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_millis(100));
         Mock::given(any())
-            .respond_with(ResponseTemplate::new(500).set_delay(std::time::Duration::from_secs(180)))
-            .expect(1)
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
             .mount(&mock_server)
             .await;
 
         // Act
         let send_result = email_client.send_mail(arrange_message()).await;
-        assert!(send_result.is_err(), "Result was: {:?}", send_result);
+
+        // Assert
+        assert_eq!(send_result.unwrap_err().provider_status(), None);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client.send_mail(arrange_message()).await;
+
+        // Assert
+        assert!(!send_result.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retryable() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_millis(50));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client.send_mail(arrange_message()).await;
+
+        // Assert
+        assert!(send_result.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_mail_with_retry_recovers_from_transient_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5))
+            .with_retry_policy(fast_retry_policy());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client.send_mail_with_retry(arrange_message()).await;
+
+        // Assert
+        assert!(send_result.is_ok(), "Result was: {:?}", send_result);
+    }
+
+    #[tokio::test]
+    async fn send_mail_with_retry_gives_up_after_max_attempts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5))
+            .with_retry_policy(fast_retry_policy());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let failure = email_client
+            .send_mail_with_retry(arrange_message())
+            .await
+            .unwrap_err();
+
+        // Assert
+        assert_eq!(failure.attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn send_mail_with_retry_does_not_retry_permanent_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = postmark_client(mock_server.uri(), Duration::from_secs(5))
+            .with_retry_policy(fast_retry_policy());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let failure = email_client
+            .send_mail_with_retry(arrange_message())
+            .await
+            .unwrap_err();
+
+        // Assert
+        assert_eq!(failure.attempts, 1);
     }
 }
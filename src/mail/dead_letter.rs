@@ -0,0 +1,232 @@
+use super::{DeliveryFailure, EmailMessage};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// An email that could not be delivered, kept for inspection and manual replay.
+///
+/// Only notices keep their bodies; see [`DeadLetterKind`].
+#[derive(serde::Serialize, Debug)]
+pub struct DeadLetter {
+    pub dead_letter_id: Uuid,
+    /// What the email was, as stored by [`DeadLetterKind::as_str`].
+    pub kind: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body_text: Option<String>,
+    pub body_html: Option<String>,
+    /// The newsletter issue the email belongs to, if it was a newsletter delivery.
+    pub newsletter_issue_id: Option<Uuid>,
+    pub subscriber_id: Option<Uuid>,
+    pub list_id: Option<Uuid>,
+    pub attempts: i32,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// What kind of email a dead letter holds, and what is needed to send it again.
+///
+/// Emails carrying a token link are stored without their bodies, since anyone listing dead
+/// letters could otherwise follow the link. Replaying one renders it again with a fresh token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeadLetterKind {
+    /// An email without links, stored as it was sent, along with the subscriber it is about if
+    /// it isn't addressed to their current address.
+    Notice { subscriber_id: Option<Uuid> },
+    /// The confirmation of a subscription to a list.
+    Confirmation { subscriber_id: Uuid, list_id: Uuid },
+    /// The welcome sent once a subscription is confirmed, which carries the management link.
+    Welcome { subscriber_id: Uuid, list_id: Uuid },
+    /// The confirmation of a change of the subscriber's address to the recipient.
+    EmailChange { subscriber_id: Uuid },
+    /// A newsletter issue, which carries the subscriber's unsubscribe link.
+    Newsletter {
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    },
+    /// An email that was stored with its token link before such emails stopped being stored.
+    /// The body was removed, so it can't be replayed.
+    Redacted,
+}
+
+impl DeadLetterKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Notice { .. } => "notice",
+            Self::Confirmation { .. } => "confirmation",
+            Self::Welcome { .. } => "welcome",
+            Self::EmailChange { .. } => "email_change",
+            Self::Newsletter { .. } => "newsletter",
+            Self::Redacted => "redacted",
+        }
+    }
+}
+
+impl DeadLetter {
+    /// The kind of email the dead letter holds.
+    pub fn kind(&self) -> DeadLetterKind {
+        // The table's check constraint guarantees that each kind has the columns it needs.
+        match (
+            self.kind.as_str(),
+            self.subscriber_id,
+            self.list_id,
+            self.newsletter_issue_id,
+        ) {
+            ("notice", subscriber_id, ..) => DeadLetterKind::Notice { subscriber_id },
+            ("confirmation", Some(subscriber_id), Some(list_id), _) => {
+                DeadLetterKind::Confirmation {
+                    subscriber_id,
+                    list_id,
+                }
+            }
+            ("welcome", Some(subscriber_id), Some(list_id), _) => DeadLetterKind::Welcome {
+                subscriber_id,
+                list_id,
+            },
+            ("email_change", Some(subscriber_id), ..) => {
+                DeadLetterKind::EmailChange { subscriber_id }
+            }
+            ("newsletter", Some(subscriber_id), _, Some(newsletter_issue_id)) => {
+                DeadLetterKind::Newsletter {
+                    newsletter_issue_id,
+                    subscriber_id,
+                }
+            }
+            _ => DeadLetterKind::Redacted,
+        }
+    }
+}
+
+/// Persist an undeliverable email to the dead-letter table, returning its ID. The body is only
+/// stored for notices.
+#[tracing::instrument(name = "Storing dead letter", skip(executor, message, failure))]
+pub async fn store_dead_letter<'e, E>(
+    executor: E,
+    message: &EmailMessage,
+    kind: &DeadLetterKind,
+    failure: &DeliveryFailure,
+) -> Result<Uuid, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let dead_letter_id = Uuid::new_v4();
+    let (subscriber_id, list_id, newsletter_issue_id) = match kind {
+        DeadLetterKind::Confirmation {
+            subscriber_id,
+            list_id,
+        }
+        | DeadLetterKind::Welcome {
+            subscriber_id,
+            list_id,
+        } => (Some(*subscriber_id), Some(*list_id), None),
+        DeadLetterKind::EmailChange { subscriber_id } => (Some(*subscriber_id), None, None),
+        DeadLetterKind::Newsletter {
+            newsletter_issue_id,
+            subscriber_id,
+        } => (Some(*subscriber_id), None, Some(*newsletter_issue_id)),
+        DeadLetterKind::Notice { subscriber_id } => (*subscriber_id, None, None),
+        DeadLetterKind::Redacted => (None, None, None),
+    };
+    let (body_text, body_html) = match kind {
+        DeadLetterKind::Notice { .. } => (Some(&message.body_text), Some(&message.body_html)),
+        _ => (None, None),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO email_dead_letters (
+            dead_letter_id, kind, recipient, subject, body_text, body_html,
+            newsletter_issue_id, subscriber_id, list_id, attempts, last_error, failed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        "#,
+        dead_letter_id,
+        kind.as_str(),
+        message.recipient.as_ref(),
+        message.subject,
+        body_text,
+        body_html,
+        newsletter_issue_id,
+        subscriber_id,
+        list_id,
+        failure.attempts as i32,
+        failure.error.to_string(),
+        Utc::now()
+    )
+    .execute(executor)
+    .await?;
+    Ok(dead_letter_id)
+}
+
+/// List every dead letter that has not been replayed yet, oldest first.
+pub async fn list_dead_letters(pool: &sqlx::PgPool) -> Result<Vec<DeadLetter>, sqlx::Error> {
+    sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT dead_letter_id, kind, recipient, subject, body_text, body_html,
+            newsletter_issue_id, subscriber_id, list_id, attempts, last_error, failed_at
+        FROM email_dead_letters
+        WHERE replayed_at IS NULL
+        ORDER BY failed_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Fetch a single dead letter that has not been replayed yet.
+pub async fn get_dead_letter(
+    dead_letter_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<Option<DeadLetter>, sqlx::Error> {
+    sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT dead_letter_id, kind, recipient, subject, body_text, body_html,
+            newsletter_issue_id, subscriber_id, list_id, attempts, last_error, failed_at
+        FROM email_dead_letters
+        WHERE dead_letter_id = $1 AND replayed_at IS NULL
+        "#,
+        dead_letter_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Record that a dead letter has been delivered by a replay.
+pub async fn mark_dead_letter_replayed(
+    dead_letter_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_dead_letters SET replayed_at = $2
+        WHERE dead_letter_id = $1
+        "#,
+        dead_letter_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record that a replay of a dead letter failed again.
+pub async fn record_failed_replay(
+    dead_letter_id: Uuid,
+    failure: &DeliveryFailure,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_dead_letters
+        SET attempts = attempts + $2, last_error = $3, failed_at = $4
+        WHERE dead_letter_id = $1
+        "#,
+        dead_letter_id,
+        failure.attempts as i32,
+        failure.error.to_string(),
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The reason an email could not be handed to the mail service.
+#[derive(Debug)]
+pub enum SendError {
+    /// A transient failure, such as a timeout, a `429` or a `5xx` response. Sending again later
+    /// may succeed.
+    Retryable(BoxError),
+    /// The mail service rejected the message outright, e.g. with a `4xx` response. Sending it
+    /// again will fail the same way.
+    Permanent(BoxError),
+}
+
+impl SendError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Retryable(_))
+    }
+
+    /// The response code the mail service answered with, if it answered at all.
+    pub fn provider_status(&self) -> Option<String> {
+        let (Self::Retryable(e) | Self::Permanent(e)) = self;
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            e.status().map(|status| status.as_str().to_owned())
+        } else if let Some(e) = e.downcast_ref::<lettre::transport::smtp::Error>() {
+            e.status().map(|code| code.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        let retryable = match e.status() {
+            Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+            None => e.is_timeout() || e.is_connect() || e.is_request(),
+        };
+        if retryable {
+            Self::Retryable(Box::new(e))
+        } else {
+            Self::Permanent(Box::new(e))
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Retryable(e) => write!(f, "Retryable send failure: {}", e),
+            Self::Permanent(e) => write!(f, "Permanent send failure: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Retryable(e) | Self::Permanent(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// A send that failed for good, either because the error was permanent or because every attempt
+/// allowed by the retry policy was used up.
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    /// How many attempts were made in total.
+    pub attempts: u32,
+    /// The error returned by the last attempt.
+    pub error: SendError,
+}
+
+impl std::fmt::Display for DeliveryFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Delivery failed after {} attempts", self.attempts)
+    }
+}
+
+impl std::error::Error for DeliveryFailure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// How failed sends are retried.
+///
+/// The delay before each retry doubles, starting from `base_delay`, and a random amount of up to
+/// `jitter` is added so that retries from several senders don't line up.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound of the random delay added to each retry.
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a retryable error after `attempts` attempts should be tried again.
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// How long to wait before the next attempt, given that `attempts` attempts have failed.
+    pub fn delay_after(&self, attempts: u32) -> Duration {
+        // Cap the exponent so that a large retry count can't overflow the delay.
+        let exponent = attempts.saturating_sub(1).min(16);
+        let backoff = self.base_delay.saturating_mul(1 << exponent);
+        let jitter_ms = self.jitter.as_millis() as u64;
+        let jitter = if jitter_ms == 0 {
+            0
+        } else {
+            rand::thread_rng().gen_range(0..=jitter_ms)
+        };
+        backoff + Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            jitter: Duration::from_millis(jitter_ms),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_each_attempt() {
+        let policy = policy(0);
+        assert_eq!(policy.delay_after(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_after(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_after(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = policy(50);
+        for _ in 0..100 {
+            let delay = policy.delay_after(2);
+            assert!(delay >= Duration::from_millis(200), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(250), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn large_attempt_counts_do_not_overflow() {
+        let policy = policy(0);
+        assert_eq!(policy.delay_after(u32::MAX), policy.delay_after(17));
+    }
+
+    #[test]
+    fn retries_stop_at_max_attempts() {
+        let policy = policy(0);
+        assert!(policy.should_retry(3));
+        assert!(!policy.should_retry(4));
+    }
+}
@@ -0,0 +1,105 @@
+use super::{EmailMessage, SendError};
+use crate::domain::ListSubscriberEmail;
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+
+mod file;
+pub use file::FileTransport;
+
+mod postmark;
+pub use postmark::PostmarkTransport;
+
+mod smtp;
+pub use smtp::SmtpTransport;
+
+/// A way of handing emails over for delivery.
+///
+/// The `EmailClient` owns one transport, chosen through `EmailClientSettings`, and relies on it to
+/// classify failures as retryable or permanent.
+#[async_trait::async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+    /// A short name for the transport, used to label metrics.
+    fn name(&self) -> &'static str;
+
+    /// Deliver the given message on behalf of `sender`.
+    async fn send(
+        &self,
+        sender: &ListSubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<(), SendError>;
+
+    /// Check that the mail service can be reached, without sending anything.
+    async fn check_reachable(&self) -> Result<(), SendError>;
+}
+
+/// Build a MIME message carrying both the plaintext and HTML bodies, for transports that deliver
+/// raw messages rather than calling an HTTP API.
+fn mime_message(
+    sender: &ListSubscriberEmail,
+    message: &EmailMessage,
+) -> Result<lettre::Message, SendError> {
+    let from: Mailbox = sender
+        .as_ref()
+        .parse()
+        .map_err(|e| SendError::Permanent(Box::new(e)))?;
+    let to: Mailbox = message
+        .recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| SendError::Permanent(Box::new(e)))?;
+    let mut builder = lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(message.subject.as_str());
+    if let Some(url) = &message.list_unsubscribe {
+        builder = builder
+            .header(ListUnsubscribe(format!("<{}>", url)))
+            .header(ListUnsubscribePost);
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            message.body_text.clone(),
+            message.body_html.clone(),
+        ))
+        .map_err(|e| SendError::Permanent(Box::new(e)))
+}
+
+/// The `List-Unsubscribe` header, holding the angle-bracketed unsubscribe URL.
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.into()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// The `List-Unsubscribe-Post` header, announcing that the unsubscribe URL takes a one-click
+/// `POST` as described by RFC 8058.
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), ONE_CLICK_UNSUBSCRIBE.into())
+    }
+}
+
+/// The value of the `List-Unsubscribe-Post` header.
+const ONE_CLICK_UNSUBSCRIBE: &str = "List-Unsubscribe=One-Click";
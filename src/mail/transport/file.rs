@@ -0,0 +1,130 @@
+use super::{mime_message, EmailTransport};
+use crate::domain::ListSubscriberEmail;
+use crate::mail::{EmailMessage, SendError};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+/// Writes each email to a `.eml` file in a directory instead of delivering it.
+///
+/// This is meant for local development, where the emails can be opened in a mail client.
+#[derive(Debug)]
+pub struct FileTransport {
+    directory: PathBuf,
+    writer: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    /// Construct a new file transport writing to `directory`, which is created if needed.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        Self {
+            writer: AsyncFileTransport::new(&directory),
+            directory,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(
+        &self,
+        sender: &ListSubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<(), SendError> {
+        let message = mime_message(sender, message)?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| SendError::Permanent(Box::new(e)))?;
+        let id = self
+            .writer
+            .send(message)
+            .await
+            .map_err(|e| SendError::Permanent(Box::new(e)))?;
+        tracing::info!("Wrote email {}.eml to {}", id, self.directory.display());
+        Ok(())
+    }
+
+    async fn check_reachable(&self) -> Result<(), SendError> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| SendError::Permanent(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileTransport;
+    use crate::domain::ListSubscriberEmail;
+    use crate::mail::{EmailClient, EmailMessage};
+
+    #[tokio::test]
+    async fn send_mail_writes_eml_file() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            ListSubscriberEmail::try_from("sender@example.com".to_owned()).unwrap(),
+            FileTransport::new(&directory),
+        );
+        let message = EmailMessage {
+            recipient: ListSubscriberEmail::try_from("recipient@example.com".to_owned()).unwrap(),
+            subject: "Subject line".into(),
+            body_text: "Plain body".into(),
+            body_html: "<p>HTML body</p>".into(),
+            list_unsubscribe: None,
+        };
+
+        // Act
+        let send_result = email_client.send_mail(message).await;
+
+        // Assert
+        assert!(send_result.is_ok(), "Result was: {:?}", send_result);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("Subject: Subject line"));
+        assert!(contents.contains("To: recipient@example.com"));
+        assert!(contents.contains("Plain body"));
+        assert!(contents.contains("<p>HTML body</p>"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_mail_carries_one_click_unsubscribe_headers() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            ListSubscriberEmail::try_from("sender@example.com".to_owned()).unwrap(),
+            FileTransport::new(&directory),
+        );
+        let message = EmailMessage {
+            recipient: ListSubscriberEmail::try_from("recipient@example.com".to_owned()).unwrap(),
+            subject: "Subject line".into(),
+            body_text: "Plain body".into(),
+            body_html: "<p>HTML body</p>".into(),
+            list_unsubscribe: Some("https://example.com/unsubscribe?token=abc".into()),
+        };
+
+        // Act
+        email_client.send_mail(message).await.unwrap();
+
+        // Assert
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let contents = std::fs::read_to_string(file).unwrap();
+        assert!(contents.contains("List-Unsubscribe: <https://example.com/unsubscribe?token=abc>"));
+        assert!(contents.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
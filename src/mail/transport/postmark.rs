@@ -0,0 +1,210 @@
+use super::{EmailTransport, ONE_CLICK_UNSUBSCRIBE};
+use crate::domain::ListSubscriberEmail;
+use crate::mail::{EmailMessage, SendError};
+use crate::telemetry::inject_trace_context;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailApiRequest {
+    from: String,
+    to: String,
+    subject: String,
+    text_body: String,
+    html_body: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailApiHeader>,
+}
+
+/// A custom header for Postmark to add to the message.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailApiHeader {
+    name: String,
+    value: String,
+}
+
+/// Sends emails through Postmark's JSON API.
+#[derive(Debug)]
+pub struct PostmarkTransport {
+    /// The HTTP client used to process the REST API requests to send emails.
+    http_client: Client,
+    /// The base API url for the mail service used to send emails.
+    api_url: String,
+    /// The API token used to authenticate with the mail application
+    auth_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    /// Construct a new Postmark transport
+    ///
+    /// # Arguments
+    ///
+    /// * `api_url` - a String representing the base API url used to send emails
+    /// * `auth_token` - an API token for the mail REST interface.
+    /// * `timeout` - Request timeout.
+    pub fn new(api_url: String, auth_token: Secret<String>, timeout: Duration) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            api_url,
+            auth_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    fn name(&self) -> &'static str {
+        "postmark"
+    }
+
+    async fn send(
+        &self,
+        sender: &ListSubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<(), SendError> {
+        let url = format!("{}/email", self.api_url);
+        let body = EmailApiRequest {
+            to: message.recipient.as_ref().to_owned(),
+            from: sender.as_ref().to_owned(),
+            subject: message.subject.clone(),
+            html_body: message.body_html.clone(),
+            text_body: message.body_text.clone(),
+            headers: match &message.list_unsubscribe {
+                Some(url) => vec![
+                    EmailApiHeader {
+                        name: "List-Unsubscribe".into(),
+                        value: format!("<{}>", url),
+                    },
+                    EmailApiHeader {
+                        name: "List-Unsubscribe-Post".into(),
+                        value: ONE_CLICK_UNSUBSCRIBE.into(),
+                    },
+                ],
+                None => Vec::new(),
+            },
+        };
+        let mut headers = HeaderMap::new();
+        inject_trace_context(&mut headers);
+        self.http_client
+            .post(url)
+            .headers(headers)
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn check_reachable(&self) -> Result<(), SendError> {
+        // Fetching the server details also proves that the token is accepted.
+        self.http_client
+            .get(format!("{}/server", self.api_url))
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailApiRequest, PostmarkTransport};
+    use crate::domain::ListSubscriberEmail;
+    use crate::mail::{EmailClient, EmailMessage};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::Fake;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::TracerProvider as _;
+    use secrecy::Secret;
+    use std::time::Duration;
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+    use wiremock::matchers::{body_json_schema, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn arrange_message() -> EmailMessage {
+        let message_body: String = Paragraph(1..4).fake();
+        EmailMessage {
+            recipient: ListSubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap(),
+            subject: Sentence(1..3).fake(),
+            body_text: message_body.clone(),
+            body_html: message_body,
+            list_unsubscribe: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn send_mail_delivers_correct_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            ListSubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap(),
+            PostmarkTransport::new(
+                mock_server.uri(),
+                Secret::new("token".into()),
+                Duration::from_secs(5),
+            ),
+        );
+
+        Mock::given(body_json_schema::<EmailApiRequest>)
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client.send_mail(arrange_message()).await;
+        assert!(send_result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_mail_propagates_trace_context() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            ListSubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap(),
+            PostmarkTransport::new(
+                mock_server.uri(),
+                Secret::new("token".into()),
+                Duration::from_secs(5),
+            ),
+        );
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        // Tracers only hold a weak reference to their provider, so it has to outlive the test.
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(header_exists("traceparent"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let send_result = email_client
+            .send_mail(arrange_message())
+            .instrument(tracing::info_span!("Sending traced email"))
+            .await;
+
+        // Assert
+        assert!(send_result.is_ok());
+    }
+}
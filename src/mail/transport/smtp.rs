@@ -0,0 +1,182 @@
+use super::{mime_message, EmailTransport};
+use crate::domain::ListSubscriberEmail;
+use crate::mail::{EmailMessage, SendError};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+/// Sends emails to an SMTP relay.
+#[derive(Debug)]
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    /// Construct a new SMTP transport
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - the relay to connect to.
+    /// * `port` - the port the relay listens on.
+    /// * `credentials` - username and password to authenticate with, if the relay requires it.
+    /// * `starttls` - whether to require upgrading the connection with STARTTLS.
+    /// * `timeout` - Connection and command timeout.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(Secret<String>, Secret<String>)>,
+        starttls: bool,
+        timeout: Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = if starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        }
+        .port(port)
+        .timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username.expose_secret().to_owned(),
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(
+        &self,
+        sender: &ListSubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<(), SendError> {
+        let message = mime_message(sender, message)?;
+        self.mailer.send(message).await?;
+        Ok(())
+    }
+
+    async fn check_reachable(&self) -> Result<(), SendError> {
+        if self.mailer.test_connection().await? {
+            Ok(())
+        } else {
+            Err(SendError::Retryable(
+                "The SMTP relay did not answer a NOOP".into(),
+            ))
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        // 5xx replies, and problems on our side of the conversation, will not go away by
+        // themselves. Everything else (4xx replies, timeouts, dropped connections) may.
+        if e.is_permanent() || e.is_client() || e.is_tls() {
+            Self::Permanent(Box::new(e))
+        } else {
+            Self::Retryable(Box::new(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmtpTransport;
+    use crate::domain::ListSubscriberEmail;
+    use crate::mail::{EmailClient, EmailMessage};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Start a bare-bones SMTP server that answers `RCPT TO` with `rcpt_reply` and returns the
+    /// port it listens on.
+    async fn start_smtp_server(rcpt_reply: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 OK"
+                } else if line.starts_with("EHLO") {
+                    "250 localhost"
+                } else if line.starts_with("RCPT TO") {
+                    rcpt_reply
+                } else if line == "DATA" {
+                    in_data = true;
+                    "354 Go ahead"
+                } else if line == "QUIT" {
+                    "221 Bye"
+                } else {
+                    "250 OK"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                writer.write_all(b"\r\n").await.unwrap();
+            }
+        });
+        port
+    }
+
+    fn smtp_client(port: u16) -> EmailClient {
+        EmailClient::new(
+            ListSubscriberEmail::try_from("sender@example.com".to_owned()).unwrap(),
+            SmtpTransport::new("127.0.0.1", port, None, false, Duration::from_secs(5)).unwrap(),
+        )
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            recipient: ListSubscriberEmail::try_from("recipient@example.com".to_owned()).unwrap(),
+            subject: "Subject line".into(),
+            body_text: "Plain body".into(),
+            body_html: "<p>HTML body</p>".into(),
+            list_unsubscribe: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn send_mail_delivers_to_relay() {
+        let port = start_smtp_server("250 OK").await;
+        let send_result = smtp_client(port).send_mail(message()).await;
+        assert!(send_result.is_ok(), "Result was: {:?}", send_result);
+    }
+
+    #[tokio::test]
+    async fn rejected_recipients_are_permanent() {
+        let port = start_smtp_server("550 No such user").await;
+        let send_result = smtp_client(port).send_mail(message()).await;
+        assert!(!send_result.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn temporary_failures_are_retryable() {
+        let port = start_smtp_server("451 Try again later").await;
+        let send_result = smtp_client(port).send_mail(message()).await;
+        assert!(send_result.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn unreachable_relays_are_retryable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let send_result = smtp_client(port).send_mail(message()).await;
+        assert!(send_result.unwrap_err().is_retryable());
+    }
+}
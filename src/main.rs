@@ -1,20 +1,176 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
 use sqlx::PgPool;
-use zero2prod::configuration::get_configuration;
+use std::path::{Path, PathBuf};
+use zero2prod::authentication::create_user;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::lists::DEFAULT_LIST_SLUG;
+use zero2prod::routes::hash_legacy_tokens;
 use zero2prod::startup::AppInfo;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::subscriber_import::{
+    import_errors, import_subscribers, write_error_report, ImportOptions, ImportStatus,
+};
+use zero2prod::telemetry::{get_otlp_tracer, get_subscriber, init_subscriber, shutdown_tracing};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server and the background workers. This is the default.
+    Serve,
+    /// Create an admin user. The password is read from standard input.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+    },
+    /// Import subscribers from a CSV file with `email` and `name` columns.
+    ///
+    /// Confirmation emails for subscribers imported as pending are sent by the server's worker.
+    ImportSubscribers {
+        /// The CSV file to import.
+        #[arg(long)]
+        file: PathBuf,
+        /// Slug of the list to import into.
+        #[arg(long, default_value = DEFAULT_LIST_SLUG)]
+        list: String,
+        /// Either `confirmed` or `pending`.
+        #[arg(long)]
+        status: ImportStatus,
+        /// Where the consent of subscribers imported as confirmed was collected.
+        #[arg(long)]
+        attestation: Option<String>,
+        /// Where to write the rows that couldn't be imported, as CSV.
+        #[arg(long)]
+        error_report: Option<PathBuf>,
+    },
+}
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    // Tracer Setup
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
 
     // Configuration
     let configuration = get_configuration().expect("Failed to get configuration");
+
+    // Tracer Setup
+    let tracer = get_otlp_tracer("zero2prod", &configuration.telemetry)
+        .context("Failed to set up the OTLP exporter.")?;
+    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout, tracer);
+    init_subscriber(subscriber);
+
+    let outcome = run_command(cli, configuration).await;
+    // Flushing the last batch of spans blocks until the export finishes.
+    tokio::task::spawn_blocking(shutdown_tracing).await?;
+    outcome
+}
+
+async fn run_command(cli: Cli, configuration: Settings) -> anyhow::Result<()> {
     // SQL Database setup
     let db_connection = PgPool::connect_lazy_with(configuration.database.with_db());
 
+    if let Some(Command::CreateAdmin { username }) = cli.command {
+        let password = read_password()?;
+        let user_id = create_user(&username, password, &db_connection).await?;
+        tracing::info!("Created admin user {} with ID {}", username, user_id);
+        return Ok(());
+    }
+    if let Some(Command::ImportSubscribers {
+        file,
+        list,
+        status,
+        attestation,
+        error_report,
+    }) = cli.command
+    {
+        let options = ImportOptions {
+            list_slug: list,
+            status,
+            attestation,
+            admin_user_id: None,
+        };
+        return import_file(
+            &file,
+            &options,
+            error_report,
+            &configuration,
+            &db_connection,
+        )
+        .await;
+    }
+
+    // Tokens stored before they were hashed at rest are converted before serving any requests.
+    // Serving without them would turn away every link holding one, so failing here is fatal.
+    let count = hash_legacy_tokens(&configuration.tokens, &db_connection)
+        .await
+        .context("Failed to hash legacy subscription tokens.")?;
+    tracing::info!("Hashed {} legacy subscription tokens", count);
+
     let app = AppInfo::new(configuration, db_connection)?;
-    app.server.await?;
+    tokio::select! {
+        outcome = app.server => outcome?,
+        outcome = app.worker => tracing::error!("Delivery worker exited: {:?}", outcome),
+        outcome = app.confirmation_worker => {
+            tracing::error!("Confirmation worker exited: {:?}", outcome)
+        }
+    }
     Ok(())
 }
+
+/// Import subscribers from the file, writing its error report out if asked to.
+async fn import_file(
+    file: &Path,
+    options: &ImportOptions,
+    error_report: Option<PathBuf>,
+    configuration: &Settings,
+    db_connection: &PgPool,
+) -> anyhow::Result<()> {
+    let input = tokio::fs::File::open(file)
+        .await
+        .with_context(|| format!("Failed to open {}.", file.display()))?;
+    let domain_policy = configuration
+        .email_domains
+        .policy()
+        .context("Failed to read the disposable email domain list.")?;
+    let summary = import_subscribers(
+        input,
+        options,
+        &configuration.tokens,
+        &domain_policy,
+        db_connection,
+    )
+    .await?;
+    tracing::info!(
+        "Import {} finished: {} rows imported, {} rows failed",
+        summary.import_id,
+        summary.imported_rows,
+        summary.failed_rows
+    );
+    if let Some(path) = error_report {
+        let errors = import_errors(summary.import_id, db_connection)
+            .await?
+            .context("The import has disappeared.")?;
+        let output = tokio::fs::File::create(&path)
+            .await
+            .with_context(|| format!("Failed to create {}.", path.display()))?;
+        write_error_report(&errors, output).await?;
+        tracing::info!("Wrote the error report to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Read a password from the first line of standard input.
+fn read_password() -> anyhow::Result<Secret<String>> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read the password from standard input.")?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]).to_string();
+    anyhow::ensure!(!password.is_empty(), "The password must not be empty.");
+    Ok(Secret::new(password))
+}
@@ -0,0 +1,286 @@
+//! Prometheus metrics.
+//!
+//! Counters and histograms are updated as the app runs: HTTP traffic by the [`RequestMetrics`]
+//! middleware, and email sends by the `EmailClient`. Gauges describing current state, such as
+//! the connection pool and subscriber counts, are refreshed whenever `/metrics` is scraped,
+//! though subscriber counts are only queried once per [`SUBSCRIBER_COUNTS_TTL`].
+//!
+//! `/metrics` is only served to scrapers presenting the configured bearer token.
+use crate::configuration::MetricsSettings;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{ContentType, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use futures_util::future::LocalBoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use secrecy::{ExposeSecret, Secret};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+/// How long subscriber counts are reused before the database is asked again.
+pub const SUBSCRIBER_COUNTS_TTL: Duration = Duration::from_secs(60);
+
+/// Every metric exposed by the app is registered here.
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "http_requests_total",
+            "HTTP requests handled, by route and status.",
+        ),
+        &["method", "route", "status"],
+    ))
+});
+
+static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "Time taken to handle HTTP requests, by route.",
+        ),
+        &["method", "route"],
+    ))
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "db_pool_connections",
+            "Connections held by the Postgres pool, by state.",
+        ),
+        &["state"],
+    ))
+});
+
+static EMAIL_SENDS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "email_sends_total",
+            "Attempts to hand an email to the provider, by outcome and provider response status.",
+        ),
+        &["transport", "outcome", "status"],
+    ))
+});
+
+static SUBSCRIBERS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "subscribers",
+            "Subscribers, by list and subscription status.",
+        ),
+        &["list", "status"],
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metrics are only registered once");
+    metric
+}
+
+/// Record one attempt to send an email.
+///
+/// `outcome` is `sent`, `retryable` or `permanent`. `status` is the provider's response code,
+/// `ok` for accepted emails, or `none` when the provider didn't answer.
+pub fn record_email_send(transport: &str, outcome: &str, status: &str) {
+    EMAIL_SENDS_TOTAL
+        .with_label_values(&[transport, outcome, status])
+        .inc();
+}
+
+/// Subscriber counts by list slug and status.
+type Counts = Vec<(String, String, i64)>;
+
+/// Subscriber counts from the last time they were queried, shared by every worker of the app.
+#[derive(Default)]
+pub struct SubscriberCounts(Mutex<Option<(Instant, Counts)>>);
+
+/// Serve every metric in the Prometheus text format, to scrapers with the configured token.
+///
+/// Without a configured token, the endpoint answers `404 Not Found` to everyone.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Serving metrics", skip_all)]
+pub async fn handle_metrics(
+    request: HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    settings: web::Data<MetricsSettings>,
+    subscriber_counts: web::Data<SubscriberCounts>,
+) -> impl Responder {
+    let expected_token = match &settings.bearer_token {
+        Some(token) => token,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    if !is_authorized(&request, expected_token) {
+        return HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, "Bearer"))
+            .finish();
+    }
+
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["active"])
+        .set(pool.size() as i64 - idle);
+
+    // Holding the lock while querying keeps concurrent scrapes from querying together.
+    let mut cached = subscriber_counts.0.lock().await;
+    let is_stale = cached
+        .as_ref()
+        .map_or(true, |(at, _)| at.elapsed() >= SUBSCRIBER_COUNTS_TTL);
+    if is_stale {
+        match count_subscribers(&pool).await {
+            Ok(counts) => *cached = Some((Instant::now(), counts)),
+            // The remaining metrics are still worth serving without the subscriber counts.
+            Err(e) => tracing::error!("Failed to count subscribers: {:?}", e),
+        }
+    }
+    if let Some((_, counts)) = cached.as_ref() {
+        SUBSCRIBERS.reset();
+        for (list, status, count) in counts {
+            SUBSCRIBERS.with_label_values(&[list, status]).set(*count);
+        }
+    }
+    drop(cached);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            TextEncoder::new()
+                .format_type()
+                .parse()
+                .expect("The Prometheus content type is a valid MIME type"),
+        ))
+        .body(buffer)
+}
+
+/// Whether the request carries the expected bearer token.
+fn is_authorized(request: &HttpRequest, expected: &Secret<String>) -> bool {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |token| {
+            token
+                .as_bytes()
+                .ct_eq(expected.expose_secret().as_bytes())
+                .into()
+        })
+}
+
+async fn count_subscribers(pool: &sqlx::PgPool) -> Result<Counts, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT l.slug, s.status, COUNT(*) AS "count!"
+        FROM list_subscriptions s
+        JOIN lists l ON l.list_id = s.list_id
+        GROUP BY l.slug, s.status
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.slug, row.status, row.count))
+        .collect())
+}
+
+/// Middleware counting and timing every request, labelled by the route pattern it matched.
+///
+/// Using the pattern rather than the path keeps the number of label values bounded, as does
+/// lumping extension methods together.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let start = Instant::now();
+            let method = method_label(req.method());
+            let outcome = service.call(req).await;
+            // Errors that escape the handlers have lost track of the request, and with it the
+            // route that matched.
+            let (route, status) = match &outcome {
+                Ok(response) => (
+                    response
+                        .request()
+                        .match_pattern()
+                        .unwrap_or_else(|| "unmatched".to_string()),
+                    response.status(),
+                ),
+                Err(e) => ("unknown".to_string(), e.as_response_error().status_code()),
+            };
+            HTTP_REQUEST_DURATION_SECONDS
+                .with_label_values(&[method, &route])
+                .observe(start.elapsed().as_secs_f64());
+            HTTP_REQUESTS_TOTAL
+                .with_label_values(&[method, &route, status.as_str()])
+                .inc();
+            outcome
+        })
+    }
+}
+
+/// The label for a request method: the method itself if it is a standard one, `other` if not.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "other",
+    }
+}
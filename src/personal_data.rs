@@ -0,0 +1,314 @@
+//! Data subject requests: exporting everything held about a subscriber, and erasing it.
+//!
+//! Erasure deletes the subscriber's row, which cascades to their list subscriptions and tokens,
+//! along with the deliveries still addressed to them. An `erased_subscribers` tombstone records
+//! who asked for the erasure and when, and keeps a keyed hash of the address so that later
+//! requests about the same address can be answered.
+use crate::configuration::TokenSettings;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Everything held about a subscriber.
+#[derive(serde::Serialize, ToSchema)]
+pub struct SubscriberExport {
+    pub subscriber: SubscriberRecord,
+    pub lists: Vec<ListSubscriptionRecord>,
+    pub tokens: Vec<TokenRecord>,
+    /// When the link to the preference page was last issued.
+    pub management_link_issued_at: Option<DateTime<Utc>>,
+    /// Newsletter deliveries still waiting to be sent.
+    pub pending_deliveries: Vec<PendingDeliveryRecord>,
+    /// Emails to the subscriber that could not be delivered.
+    pub failed_deliveries: Vec<FailedDeliveryRecord>,
+    pub exported_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ListSubscriptionRecord {
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+/// A confirmation token. Only a hash of the token itself is stored, so it isn't part of the
+/// export.
+#[derive(serde::Serialize, ToSchema)]
+pub struct TokenRecord {
+    /// The list the token confirms a subscription to.
+    pub list: Option<String>,
+    /// The address the token confirms a change to.
+    pub new_email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct PendingDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub attempts: i32,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct FailedDeliveryRecord {
+    pub subject: String,
+    pub attempts: i32,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+    pub replayed_at: Option<DateTime<Utc>>,
+}
+
+/// Who asked for an erasure.
+#[derive(Clone, Copy, Debug)]
+pub enum ErasureRequester {
+    /// The subscriber, through their management link.
+    Subscriber,
+    /// An admin user, on behalf of the subscriber.
+    Admin(Uuid),
+}
+
+/// The record left behind by an erasure.
+#[derive(serde::Serialize, Debug)]
+pub struct Tombstone {
+    pub tombstone_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub erased_at: DateTime<Utc>,
+}
+
+/// Find a subscriber by address, as typed by whoever is asking on their behalf.
+pub async fn find_subscriber_by_email(
+    canonical_email: &str,
+    pool: &sqlx::PgPool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE email_canonical = $1
+        "#,
+        canonical_email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| row.id))
+}
+
+/// Gather everything held about the subscriber, or `None` if they don't exist.
+#[tracing::instrument(name = "Exporting subscriber data", skip(pool))]
+pub async fn export_subscriber(
+    subscriber_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<Option<SubscriberExport>, sqlx::Error> {
+    let subscriber = match sqlx::query!(
+        r#"
+        SELECT id, email, email_canonical, name, subscribed_at, paused_until FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+
+    let lists = sqlx::query_as!(
+        ListSubscriptionRecord,
+        r#"
+        SELECT l.slug AS list, s.status, s.subscribed_at, s.unsubscribed_at
+        FROM list_subscriptions s
+        JOIN lists l ON l.list_id = s.list_id
+        WHERE s.subscriber_id = $1
+        ORDER BY s.subscribed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tokens = sqlx::query_as!(
+        TokenRecord,
+        r#"
+        SELECT l.slug AS "list?", t.new_email, t.created_at, t.expires_at
+        FROM tokens t
+        LEFT JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let management_link_issued_at = sqlx::query!(
+        r#"
+        SELECT created_at FROM management_tokens
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.created_at);
+    let pending_deliveries = sqlx::query_as!(
+        PendingDeliveryRecord,
+        r#"
+        SELECT q.newsletter_issue_id, i.title, q.n_retries AS attempts
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE lower(q.subscriber_email) = $1
+        ORDER BY i.published_at
+        "#,
+        subscriber.email_canonical
+    )
+    .fetch_all(pool)
+    .await?;
+    let failed_deliveries = sqlx::query_as!(
+        FailedDeliveryRecord,
+        r#"
+        SELECT subject, attempts, last_error, failed_at, replayed_at
+        FROM email_dead_letters
+        WHERE lower(recipient) = $1 OR subscriber_id = $2
+        ORDER BY failed_at
+        "#,
+        subscriber.email_canonical,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(SubscriberExport {
+        subscriber: SubscriberRecord {
+            id: subscriber.id,
+            email: subscriber.email,
+            name: subscriber.name,
+            subscribed_at: subscriber.subscribed_at,
+            paused_until: subscriber.paused_until,
+        },
+        lists,
+        tokens,
+        management_link_issued_at,
+        pending_deliveries,
+        failed_deliveries,
+        exported_at: Utc::now(),
+    }))
+}
+
+/// Erase the subscriber and everything addressed to them, leaving a tombstone behind. Returns
+/// `None` if the subscriber doesn't exist.
+#[tracing::instrument(name = "Erasing subscriber", skip(settings, pool))]
+pub async fn erase_subscriber(
+    subscriber_id: Uuid,
+    requested_by: ErasureRequester,
+    settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<Option<Tombstone>, sqlx::Error> {
+    let mut txn = pool.begin().await?;
+    // Tokens and list subscriptions go along with the row.
+    let canonical_email = match sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE id = $1
+        RETURNING email_canonical
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut txn)
+    .await?
+    {
+        Some(row) => row.email_canonical,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE lower(subscriber_email) = $1
+        "#,
+        canonical_email
+    )
+    .execute(&mut txn)
+    .await?;
+    // Dead letters about the subscriber are matched by ID as well, since the notice sent to their
+    // old address when it changed isn't addressed to them any more.
+    sqlx::query!(
+        r#"
+        DELETE FROM email_dead_letters
+        WHERE lower(recipient) = $1 OR subscriber_id = $2
+        "#,
+        canonical_email,
+        subscriber_id
+    )
+    .execute(&mut txn)
+    .await?;
+
+    let (requester, admin_user_id) = match requested_by {
+        ErasureRequester::Subscriber => ("subscriber", None),
+        ErasureRequester::Admin(user_id) => ("admin", Some(user_id)),
+    };
+    let tombstone = Tombstone {
+        tombstone_id: Uuid::new_v4(),
+        subscriber_id,
+        erased_at: Utc::now(),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO erased_subscribers (
+            tombstone_id, subscriber_id, email_hash, requested_by, admin_user_id, erased_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        tombstone.tombstone_id,
+        tombstone.subscriber_id,
+        hash_email(&canonical_email, settings),
+        requester,
+        admin_user_id,
+        tombstone.erased_at
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+    Ok(Some(tombstone))
+}
+
+/// Hash a canonical address for the tombstone table. The hash is keyed with the token secret, so
+/// that addresses can't be recovered from it by hashing guesses.
+pub(crate) fn hash_email(canonical_email: &str, settings: &TokenSettings) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(settings.hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(canonical_email.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn settings(secret: &str) -> TokenSettings {
+        TokenSettings {
+            ttl_secs: 60,
+            hmac_secret: Secret::new(secret.into()),
+        }
+    }
+
+    #[test]
+    fn email_hash_is_keyed() {
+        let email = "user@example.com";
+        let hash = hash_email(email, &settings("key"));
+        assert!(!hash.contains(email));
+        assert_eq!(hash, hash_email(email, &settings("key")));
+        assert_ne!(hash, hash_email(email, &settings("other key")));
+    }
+}
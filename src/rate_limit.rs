@@ -0,0 +1,14 @@
+//! Rate limiting for the subscription endpoints.
+//!
+//! Every subscription sends an email, so requests are limited per client IP and per target
+//! email address. Each key gets a token bucket, kept in a pluggable [`RateLimitStore`]; requests
+//! that find their bucket empty are answered with `429 Too Many Requests` and a `Retry-After`
+//! header.
+mod bucket;
+pub use bucket::*;
+
+mod middleware;
+pub use middleware::*;
+
+mod store;
+pub use store::*;
@@ -0,0 +1,127 @@
+use crate::configuration::BucketSettings;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// The state of a single token bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Whether a request may go ahead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl TokenBucket {
+    /// A bucket holding as many tokens as it can.
+    pub fn full(settings: &BucketSettings, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: settings.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refill the bucket for the time elapsed since it was last updated, then try to take a
+    /// token from it.
+    pub fn take(self, settings: &BucketSettings, now: DateTime<Utc>) -> (Self, Decision) {
+        let interval = settings.refill_interval().as_secs_f64();
+        let elapsed = (now - self.updated_at)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+            .as_secs_f64();
+        let refilled = if interval > 0.0 {
+            elapsed / interval
+        } else {
+            f64::INFINITY
+        };
+        let tokens = (self.tokens + refilled).min(settings.capacity as f64);
+
+        if tokens >= 1.0 {
+            let bucket = Self {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            (bucket, Decision::Allowed)
+        } else {
+            let bucket = Self {
+                tokens,
+                updated_at: now,
+            };
+            let retry_after = Duration::from_secs_f64((1.0 - tokens) * interval);
+            (bucket, Decision::Limited { retry_after })
+        }
+    }
+
+    /// When the bucket will have refilled completely, at which point it no longer needs to be
+    /// kept around.
+    pub fn full_at(&self, settings: &BucketSettings) -> DateTime<Utc> {
+        let missing = (settings.capacity as f64 - self.tokens).max(0.0);
+        let refill_time =
+            Duration::from_secs_f64(missing * settings.refill_interval().as_secs_f64());
+        chrono::Duration::from_std(refill_time)
+            .ok()
+            .and_then(|refill_time| self.updated_at.checked_add_signed(refill_time))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: BucketSettings = BucketSettings {
+        capacity: 2,
+        refill_interval_secs: 60,
+    };
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let now = Utc::now();
+        let bucket = TokenBucket::full(&SETTINGS, now);
+        let (bucket, first) = bucket.take(&SETTINGS, now);
+        let (bucket, second) = bucket.take(&SETTINGS, now);
+        let (_, third) = bucket.take(&SETTINGS, now);
+        assert_eq!(first, Decision::Allowed);
+        assert_eq!(second, Decision::Allowed);
+        assert_eq!(
+            third,
+            Decision::Limited {
+                retry_after: Duration::from_secs(60)
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let now = Utc::now();
+        let empty = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+        let (_, decision) = empty.take(&SETTINGS, now + chrono::Duration::seconds(30));
+        assert_eq!(
+            decision,
+            Decision::Limited {
+                retry_after: Duration::from_secs(30)
+            }
+        );
+        let (_, decision) = empty.take(&SETTINGS, now + chrono::Duration::seconds(60));
+        assert_eq!(decision, Decision::Allowed);
+    }
+
+    #[test]
+    fn buckets_never_exceed_their_capacity() {
+        let now = Utc::now();
+        let bucket = TokenBucket::full(&SETTINGS, now);
+        let (bucket, _) = bucket.take(&SETTINGS, now + chrono::Duration::days(1));
+        assert_eq!(bucket.tokens, 1.0);
+        assert_eq!(
+            bucket.full_at(&SETTINGS),
+            now + chrono::Duration::days(1) + chrono::Duration::seconds(60)
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Decision, RateLimitStore};
+use crate::configuration::RateLimitSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::error::AppError;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use futures_util::Stream;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Middleware rate limiting requests by client IP, then by the `email` field of their form or
+/// JSON body.
+///
+/// If the store can't be reached, requests are let through rather than rejected.
+#[derive(Clone)]
+pub struct RateLimit {
+    store: RateLimitStore,
+    settings: Arc<RateLimitSettings>,
+}
+
+impl RateLimit {
+    pub fn new(store: RateLimitStore, settings: RateLimitSettings) -> Self {
+        Self {
+            store,
+            settings: Arc::new(settings),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limit = self.limit.clone();
+        Box::pin(async move {
+            if let Some(ip) = client_ip(&req, limit.settings.trust_forwarded_for) {
+                let key = format!("ip:{}", ip);
+                if let Some(e) = check(&limit.store, &key, &limit.settings.per_ip).await {
+                    return Ok(req.error_response(e).map_into_right_body());
+                }
+            }
+
+            // The body has to be read to find the email, then handed back to the handler.
+            let body = match req.extract::<Bytes>().await {
+                Ok(body) => body,
+                Err(e) => return Ok(req.error_response(e).map_into_right_body()),
+            };
+            let email = target_email(&req, &body);
+            req.set_payload(bytes_to_payload(body));
+            if let Some(email) = email {
+                let key = format!("email:{}", email);
+                if let Some(e) = check(&limit.store, &key, &limit.settings.per_email).await {
+                    return Ok(req.error_response(e).map_into_right_body());
+                }
+            }
+
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// Take a token for `key`, returning the error to answer with if there was none left.
+async fn check(
+    store: &RateLimitStore,
+    key: &str,
+    settings: &crate::configuration::BucketSettings,
+) -> Option<AppError> {
+    match store.take(key, settings).await {
+        Ok(Decision::Allowed) => None,
+        Ok(Decision::Limited { retry_after }) => Some(AppError::RateLimited(retry_after)),
+        Err(e) => {
+            tracing::error!("Failed to check the rate limit for {}: {:?}", key, e);
+            None
+        }
+    }
+}
+
+fn client_ip(req: &ServiceRequest, trust_forwarded_for: bool) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| req.headers().get("X-Forwarded-For"))
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+    forwarded.or_else(|| req.peer_addr().map(|addr| addr.ip()))
+}
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    #[serde(default)]
+    email: String,
+}
+
+/// The canonical form of the `email` field of a form or JSON body, if there is one.
+fn target_email(req: &ServiceRequest, body: &[u8]) -> Option<String> {
+    let field: EmailField = match req.content_type() {
+        "application/json" => serde_json::from_slice(body).ok()?,
+        "application/x-www-form-urlencoded" => serde_urlencoded::from_bytes(body).ok()?,
+        _ => return None,
+    };
+    // Invalid addresses never get an email, so only the per-IP limit applies to them.
+    ListSubscriberEmail::try_from(field.email)
+        .ok()
+        .map(|email| email.canonical().to_owned())
+}
+
+fn bytes_to_payload(body: Bytes) -> Payload {
+    let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+        Box::pin(futures_util::stream::once(async move { Ok(body) }));
+    Payload::from(stream)
+}
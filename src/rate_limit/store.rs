@@ -0,0 +1,158 @@
+use super::{Decision, TokenBucket};
+use crate::configuration::BucketSettings;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The rate limit store picked in the configuration.
+#[derive(Clone)]
+pub enum RateLimitStore {
+    Postgres(PgRateLimitStore),
+    Memory(InMemoryRateLimitStore),
+}
+
+impl RateLimitStore {
+    /// Take a token from the bucket stored under `key`, creating a full one if there is none.
+    pub async fn take(
+        &self,
+        key: &str,
+        settings: &BucketSettings,
+    ) -> Result<Decision, anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.take(key, settings).await,
+            Self::Memory(store) => Ok(store.take(key, settings)),
+        }
+    }
+}
+
+/// Rate limit store backed by the `rate_limit_buckets` table, so that every instance of the app
+/// enforces the same limits.
+#[derive(Clone)]
+pub struct PgRateLimitStore {
+    pool: sqlx::PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[tracing::instrument(name = "Taking a rate limit token", skip(self, settings))]
+    async fn take(&self, key: &str, settings: &BucketSettings) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        // Full buckets behave exactly like missing ones, so they can go. Rows locked by
+        // concurrent requests are left alone rather than waited for.
+        sqlx::query!(
+            r#"
+            DELETE FROM rate_limit_buckets
+            WHERE key IN (
+                SELECT key FROM rate_limit_buckets
+                WHERE full_at < $1
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let mut txn = self.pool.begin().await?;
+        let full = TokenBucket::full(settings, now);
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            key,
+            full.tokens,
+            now
+        )
+        .execute(&mut txn)
+        .await?;
+        // The row lock serialises concurrent requests for the same key.
+        let stored = sqlx::query!(
+            r#"
+            SELECT tokens, updated_at FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_one(&mut txn)
+        .await?;
+        let bucket = TokenBucket {
+            tokens: stored.tokens,
+            updated_at: stored.updated_at,
+        };
+
+        let (bucket, decision) = bucket.take(settings, now);
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = $3, full_at = $4
+            WHERE key = $1
+            "#,
+            key,
+            bucket.tokens,
+            bucket.updated_at,
+            bucket.full_at(settings)
+        )
+        .execute(&mut txn)
+        .await?;
+        txn.commit().await?;
+        Ok(decision)
+    }
+}
+
+/// Buckets along with the time they will be full again, by key.
+type BucketMap = HashMap<String, (TokenBucket, DateTime<Utc>)>;
+
+/// Buckets kept in memory before full ones are dropped.
+const MEMORY_PURGE_THRESHOLD: usize = 10_000;
+
+/// Rate limit store keeping buckets in process memory.
+///
+/// Limits are enforced per instance, so a deployment with several instances lets through
+/// proportionally more requests.
+#[derive(Clone, Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Arc<Mutex<BucketMap>>,
+}
+
+impl InMemoryRateLimitStore {
+    fn take(&self, key: &str, settings: &BucketSettings) -> Decision {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MEMORY_PURGE_THRESHOLD {
+            buckets.retain(|_, (_, full_at)| *full_at >= now);
+        }
+        let bucket = buckets
+            .get(key)
+            .map(|(bucket, _)| *bucket)
+            .unwrap_or_else(|| TokenBucket::full(settings, now));
+        let (bucket, decision) = bucket.take(settings, now);
+        buckets.insert(key.to_owned(), (bucket, bucket.full_at(settings)));
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_keeps_keys_apart() {
+        let store = InMemoryRateLimitStore::default();
+        let settings = BucketSettings {
+            capacity: 1,
+            refill_interval_secs: 60,
+        };
+        assert_eq!(store.take("a", &settings), Decision::Allowed);
+        assert!(matches!(
+            store.take("a", &settings),
+            Decision::Limited { .. }
+        ));
+        assert_eq!(store.take("b", &settings), Decision::Allowed);
+    }
+}
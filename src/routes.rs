@@ -2,10 +2,16 @@
 //!
 //! This module contains the handlers for the various endpoints exposed by this application's REST
 //! API.
+mod admin;
+mod api;
 mod greet;
 mod health_check;
+mod login;
 mod subscriptions;
 
+pub use admin::*;
+pub use api::*;
 pub use greet::*;
 pub use health_check::*;
+pub use login::*;
 pub use subscriptions::*;
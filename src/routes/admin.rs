@@ -0,0 +1,22 @@
+//! Endpoints of the admin area. Every route in here sits behind
+//! [`RequireLogin`](crate::authentication::RequireLogin).
+mod dashboard;
+pub use dashboard::*;
+
+mod dead_letters;
+pub use dead_letters::*;
+
+mod imports;
+pub use imports::*;
+
+mod lists;
+pub use lists::*;
+
+mod logout;
+pub use logout::*;
+
+mod newsletters;
+pub use newsletters::*;
+
+mod subscribers;
+pub use subscribers::*;
@@ -0,0 +1,31 @@
+use crate::authentication::{get_username, UserId};
+use crate::error::AppError;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+
+/// Landing page of the admin area, greeting the logged in user.
+#[tracing::instrument(name = "Admin dashboard", skip(pool))]
+pub async fn handle_admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let username = get_username(**user_id, &pool).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {}!</p>
+    <form action="/admin/logout" method="post">
+        <button type="submit">Logout</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&username)
+        )))
+}
@@ -0,0 +1,238 @@
+use crate::authentication::UserId;
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::error::AppError;
+use crate::idempotency::{get_idempotency_key, save_response, try_processing, NextAction};
+use crate::issue_delivery_worker::render_issue;
+use crate::mail::{
+    get_dead_letter, list_dead_letters, mark_dead_letter_replayed, record_failed_replay,
+    DeadLetter, DeadLetterKind, EmailClient, EmailMessage,
+};
+use crate::routes::{
+    confirmation_message, email_change_message, insert_email_change_token, management_link,
+    rotate_token_for_id, welcome_message,
+};
+use crate::startup::AppBaseUrl;
+use actix_web::{web, HttpRequest, HttpResponse};
+use uuid::Uuid;
+
+/// List the emails that could not be delivered and have not been replayed yet.
+#[tracing::instrument(name = "Listing dead letters", skip(pool))]
+pub async fn handle_list_dead_letters(
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let dead_letters = list_dead_letters(&pool).await?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+/// Try to deliver a dead letter again.
+///
+/// The email is sent with the usual retry policy. On success the dead letter is marked as
+/// replayed; otherwise its attempt count and last error are updated.
+///
+/// Emails that carried a token link are rendered again with a freshly issued token, as long as
+/// the subscription is still in the state they were sent for. Anything else, including dead
+/// letters redacted because they held a token link, is answered with `422 Unprocessable Entity`.
+///
+/// Requests carrying an `Idempotency-Key` header are only processed once per key. Failed replays,
+/// including unknown dead letters, are not remembered, so retrying one with the same key tries
+/// again.
+#[tracing::instrument(
+    name = "Replaying dead letter",
+    skip(request, pool, email_client, base_url, token_settings, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn handle_replay_dead_letter(
+    request: HttpRequest,
+    dead_letter_id: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<AppBaseUrl>,
+    token_settings: web::Data<TokenSettings>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AppError> {
+    let dead_letter_id = dead_letter_id.into_inner();
+    let replay = || {
+        replay_dead_letter(
+            dead_letter_id,
+            &pool,
+            &email_client,
+            &base_url,
+            &token_settings,
+        )
+    };
+    let idempotency_key = match get_idempotency_key(&request).map_err(AppError::Validation)? {
+        Some(key) => key,
+        None => return replay().await,
+    };
+
+    // The claimed key stays locked until the response is saved, which keeps concurrent
+    // duplicates from sending the email twice.
+    let txn = match try_processing(&pool, &idempotency_key, **user_id).await? {
+        NextAction::StartProcessing(txn) => *txn,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            tracing::info!("Replaying saved response for a duplicate request");
+            return Ok(saved_response);
+        }
+    };
+    // On error, dropping the transaction releases the key for another attempt.
+    let response = replay().await?;
+    Ok(save_response(txn, &idempotency_key, **user_id, response).await?)
+}
+
+async fn replay_dead_letter(
+    dead_letter_id: Uuid,
+    pool: &sqlx::PgPool,
+    email_client: &EmailClient,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+) -> Result<HttpResponse, AppError> {
+    let dead_letter = get_dead_letter(dead_letter_id, pool)
+        .await?
+        .ok_or(AppError::NotFound("dead letter"))?;
+    let message = replay_message(dead_letter, pool, base_url, token_settings).await?;
+
+    if let Err(failure) = email_client.send_mail_with_retry(message).await {
+        if let Err(e) = record_failed_replay(dead_letter_id, &failure, pool).await {
+            tracing::error!("Failed to record failed replay: {:?}", e);
+        }
+        return Err(AppError::Email(failure));
+    }
+    mark_dead_letter_replayed(dead_letter_id, pool).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Rebuild the email a dead letter holds, issuing a fresh token for emails that carry one.
+async fn replay_message(
+    dead_letter: DeadLetter,
+    pool: &sqlx::PgPool,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+) -> Result<EmailMessage, AppError> {
+    let kind = dead_letter.kind();
+    let recipient = ListSubscriberEmail::try_from(dead_letter.recipient).map_err(|e| {
+        AppError::Unprocessable(format!("The dead letter has an invalid recipient: {}", e))
+    })?;
+    match kind {
+        DeadLetterKind::Notice { .. } => match (dead_letter.body_text, dead_letter.body_html) {
+            (Some(body_text), Some(body_html)) => Ok(EmailMessage {
+                recipient,
+                subject: dead_letter.subject,
+                body_text,
+                body_html,
+                list_unsubscribe: None,
+            }),
+            _ => Err(AppError::Unprocessable(
+                "The dead letter has no body to send.".into(),
+            )),
+        },
+        DeadLetterKind::Confirmation {
+            subscriber_id,
+            list_id,
+        } => {
+            let mut txn = pool.begin().await?;
+            let subscription = sqlx::query!(
+                r#"
+                SELECT ls.status, l.name AS list_name
+                FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = $1 AND ls.list_id = $2
+                FOR UPDATE OF ls
+                "#,
+                subscriber_id,
+                list_id
+            )
+            .fetch_optional(&mut txn)
+            .await?;
+            let list_name = match subscription {
+                Some(row) if row.status == "pending" => row.list_name,
+                _ => {
+                    return Err(AppError::Unprocessable(
+                        "The subscription is no longer waiting for confirmation.".into(),
+                    ))
+                }
+            };
+            let token =
+                rotate_token_for_id(subscriber_id, list_id, token_settings, &mut txn).await?;
+            txn.commit().await?;
+            Ok(confirmation_message(
+                recipient, &list_name, &token, base_url,
+            ))
+        }
+        DeadLetterKind::Welcome {
+            subscriber_id,
+            list_id,
+        } => {
+            let subscription = sqlx::query!(
+                r#"
+                SELECT ls.status, l.name AS list_name
+                FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = $1 AND ls.list_id = $2
+                "#,
+                subscriber_id,
+                list_id
+            )
+            .fetch_optional(pool)
+            .await?;
+            let list_name = match subscription {
+                Some(row) if row.status == "confirmed" => row.list_name,
+                _ => {
+                    return Err(AppError::Unprocessable(
+                        "The subscription is no longer confirmed.".into(),
+                    ))
+                }
+            };
+            let manage_link =
+                management_link(subscriber_id, token_settings, base_url, pool).await?;
+            Ok(welcome_message(recipient, &list_name, &manage_link))
+        }
+        DeadLetterKind::EmailChange { subscriber_id } => {
+            let mut txn = pool.begin().await?;
+            let token = insert_email_change_token(
+                subscriber_id,
+                recipient.as_ref(),
+                token_settings,
+                &mut txn,
+            )
+            .await?;
+            txn.commit().await?;
+            Ok(email_change_message(recipient, &token, base_url))
+        }
+        DeadLetterKind::Newsletter {
+            newsletter_issue_id,
+            subscriber_id,
+        } => {
+            let subscribed = sqlx::query!(
+                r#"
+                SELECT ls.status
+                FROM newsletter_issues i
+                JOIN list_subscriptions ls ON ls.list_id = i.list_id
+                WHERE i.newsletter_issue_id = $1 AND ls.subscriber_id = $2
+                "#,
+                newsletter_issue_id,
+                subscriber_id
+            )
+            .fetch_optional(pool)
+            .await?
+            .map_or(false, |row| row.status == "confirmed");
+            if !subscribed {
+                return Err(AppError::Unprocessable(
+                    "The subscriber is no longer subscribed to the issue's list.".into(),
+                ));
+            }
+            Ok(render_issue(
+                &mut *pool.acquire().await?,
+                newsletter_issue_id,
+                subscriber_id,
+                recipient,
+                base_url,
+                token_settings,
+            )
+            .await?)
+        }
+        DeadLetterKind::Redacted => Err(AppError::Unprocessable(
+            "The dead letter held a token link and was redacted, so it can't be replayed.".into(),
+        )),
+    }
+}
@@ -0,0 +1,112 @@
+use crate::authentication::UserId;
+use crate::configuration::TokenSettings;
+use crate::domain::EmailDomainPolicy;
+use crate::error::AppError;
+use crate::lists::DEFAULT_LIST_SLUG;
+use crate::subscriber_import::{
+    import_errors, import_subscribers, write_error_report, ImportError, ImportOptions, ImportStatus,
+};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, LOCATION};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use anyhow::anyhow;
+use futures_util::{stream, StreamExt};
+use std::io;
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+/// How to import the uploaded file.
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    /// Slug of the list to import into. Defaults to the `default` list.
+    list: Option<String>,
+    status: ImportStatus,
+    /// Where the consent of subscribers imported as confirmed was collected.
+    attestation: Option<String>,
+}
+
+/// Import subscribers from the CSV in the request body, answering with `201 Created` and a
+/// summary of the import.
+///
+/// The body is read as it arrives rather than buffered. Rows that couldn't be imported are
+/// listed by the error report the `Location` header points to.
+#[tracing::instrument(
+    name = "Importing subscribers",
+    skip(payload, params, pool, token_settings, domain_policy),
+    fields(user_id = %*user_id)
+)]
+pub async fn handle_import_subscribers(
+    mut payload: web::Payload,
+    params: web::Query<ImportParameters>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+    domain_policy: web::Data<EmailDomainPolicy>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AppError> {
+    let params = params.into_inner();
+    let options = ImportOptions {
+        list_slug: params.list.unwrap_or_else(|| DEFAULT_LIST_SLUG.into()),
+        status: params.status,
+        attestation: params.attestation,
+        admin_user_id: Some(**user_id),
+    };
+
+    // The CSV reader needs an input that is `Send`, which the payload isn't, so its chunks are
+    // handed over through a channel while the import runs.
+    let (sender, receiver) = tokio::sync::mpsc::channel::<io::Result<Bytes>>(8);
+    let forward = async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()));
+            if sender.send(chunk).await.is_err() {
+                // The import stopped reading.
+                break;
+            }
+        }
+    };
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let input = StreamReader::new(Box::pin(chunks));
+    let (_, summary) = tokio::join!(
+        forward,
+        import_subscribers(input, &options, &token_settings, &domain_policy, &pool)
+    );
+
+    let summary = summary.map_err(|e| match e {
+        ImportError::Invalid(message) => AppError::Validation(message),
+        ImportError::UnknownList => AppError::NotFound("mailing list"),
+        ImportError::Csv(e) => AppError::Validation(format!("The CSV could not be read: {}", e)),
+        ImportError::Database(e) => AppError::Database(e),
+    })?;
+    Ok(HttpResponse::Created()
+        .insert_header((
+            LOCATION,
+            format!("/admin/imports/{}/errors", summary.import_id),
+        ))
+        .json(summary))
+}
+
+/// Download the rows of an import that couldn't be imported, as CSV.
+#[tracing::instrument(name = "Downloading import error report", skip(pool))]
+pub async fn handle_import_error_report(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let import_id = import_id.into_inner();
+    let errors = import_errors(import_id, &pool)
+        .await?
+        .ok_or(AppError::NotFound("import"))?;
+    let report = write_error_report(&errors, Vec::new())
+        .await
+        .map_err(|e| anyhow!("Failed to write the error report: {}", e))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "import-{}-errors.csv",
+                import_id
+            ))],
+        })
+        .body(report))
+}
@@ -0,0 +1,58 @@
+use crate::domain::ListSlug;
+use crate::error::{AppError, FieldErrors};
+use crate::lists::{all_lists, insert_list};
+use actix_web::{web, HttpResponse};
+
+/// The fields of a new mailing list.
+#[derive(serde::Deserialize)]
+pub struct NewList {
+    #[serde(default)]
+    slug: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// List every mailing list.
+#[tracing::instrument(name = "Listing mailing lists", skip(pool))]
+pub async fn handle_list_lists(pool: web::Data<sqlx::PgPool>) -> Result<HttpResponse, AppError> {
+    let lists = all_lists(&pool).await?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+/// Create a mailing list, answering with `201 Created` and the new list.
+///
+/// Slugs are unique; reusing one is answered with `409 Conflict`.
+#[tracing::instrument(
+    name = "Creating mailing list",
+    skip(body, pool),
+    fields(slug = %body.slug)
+)]
+pub async fn handle_create_list(
+    body: web::Json<NewList>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner();
+    let mut errors = FieldErrors::default();
+    let slug = ListSlug::try_from(body.slug)
+        .map_err(|e| errors.add("slug", e))
+        .ok();
+    let name = body.name.trim();
+    if name.is_empty() {
+        errors.add("name", "The list name must not be blank.".into());
+    }
+    let slug = match slug {
+        Some(slug) if errors.is_empty() => slug,
+        _ => return Err(AppError::InvalidFields(errors)),
+    };
+
+    match insert_list(&slug, name, &pool).await {
+        Ok(list) => Ok(HttpResponse::Created().json(list)),
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505") => {
+            Err(AppError::Conflict(format!(
+                "A list with slug {} already exists.",
+                slug.as_ref()
+            )))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,13 @@
+use crate::authentication::TypedSession;
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpResponse, Responder};
+
+/// Log the current user out and send them back to the login form.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Logging out", skip(session))]
+pub async fn handle_logout(session: TypedSession) -> impl Responder {
+    session.log_out();
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .finish()
+}
@@ -0,0 +1,120 @@
+use crate::authentication::UserId;
+use crate::error::AppError;
+use crate::idempotency::{get_idempotency_key, save_response, try_processing, NextAction};
+use crate::lists::{get_list_by_slug, DEFAULT_LIST_SLUG};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use uuid::Uuid;
+
+/// The contents of a newsletter issue.
+#[derive(serde::Deserialize)]
+pub struct NewsletterData {
+    title: String,
+    body_text: String,
+    body_html: String,
+    /// Slug of the list the issue goes to. Defaults to the `default` list.
+    list: Option<String>,
+}
+
+/// Publish a newsletter issue to every confirmed subscriber of a list.
+///
+/// The issue is stored and one delivery task is queued per confirmed subscriber of the list;
+/// pending and unsubscribed subscribers are skipped, as are subscribers who paused delivery.
+/// Unknown lists are answered with `404`. Delivery itself happens in the background, so the
+/// endpoint answers with `202 Accepted` once the tasks are queued.
+///
+/// Requests carrying an `Idempotency-Key` header are only processed once per key; retries get
+/// the original response back and don't queue the issue again.
+#[tracing::instrument(
+    name = "Publishing newsletter issue",
+    skip(request, body, pool, user_id),
+    fields(title = %body.title, user_id = %*user_id)
+)]
+pub async fn handle_publish_newsletter(
+    request: HttpRequest,
+    body: web::Json<NewsletterData>,
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AppError> {
+    let idempotency_key = get_idempotency_key(&request).map_err(AppError::Validation)?;
+    let list_slug = body.list.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let list = get_list_by_slug(list_slug, &pool)
+        .await?
+        .ok_or(AppError::NotFound("mailing list"))?;
+    let mut txn = match &idempotency_key {
+        Some(key) => match try_processing(&pool, key, **user_id).await? {
+            NextAction::StartProcessing(txn) => *txn,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                tracing::info!("Replaying saved response for a duplicate request");
+                return Ok(saved_response);
+            }
+        },
+        None => pool.begin().await?,
+    };
+
+    let issue_id = insert_newsletter_issue(&body, list.list_id, &mut txn).await?;
+    let count = enqueue_delivery_tasks(issue_id, list.list_id, &mut txn).await?;
+    tracing::info!("Queued {} deliveries", count);
+
+    let response = HttpResponse::Accepted().finish();
+    match idempotency_key {
+        Some(key) => Ok(save_response(txn, &key, **user_id, response).await?),
+        None => {
+            txn.commit().await?;
+            Ok(response)
+        }
+    }
+}
+
+/// Store a newsletter issue so that the delivery worker can render it later.
+#[tracing::instrument(name = "Storing newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    issue: &NewsletterData,
+    list_id: Uuid,
+    txn: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, list_id, title, body_text, body_html, published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        issue.title,
+        issue.body_text,
+        issue.body_html,
+        Utc::now()
+    )
+    .execute(txn)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Queue one delivery task per confirmed subscriber of the list, returning the number of tasks
+/// queued.
+#[tracing::instrument(name = "Queueing delivery tasks", skip(txn))]
+async fn enqueue_delivery_tasks(
+    newsletter_issue_id: Uuid,
+    list_id: Uuid,
+    txn: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, subscriber_id)
+        SELECT $1, s.email, s.id
+        FROM subscriptions s
+        JOIN list_subscriptions l ON l.subscriber_id = s.id
+        WHERE l.list_id = $2
+            AND l.status = 'confirmed'
+            AND (s.paused_until IS NULL OR s.paused_until <= now())
+        "#,
+        newsletter_issue_id,
+        list_id,
+    )
+    .execute(txn)
+    .await?;
+    Ok(result.rows_affected())
+}
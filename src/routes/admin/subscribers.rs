@@ -0,0 +1,132 @@
+use crate::authentication::UserId;
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::error::{AppError, FieldErrors};
+use crate::lists::get_list_by_slug;
+use crate::personal_data::{
+    erase_subscriber, export_subscriber, find_subscriber_by_email, ErasureRequester,
+};
+use crate::subscriber_export::{
+    export_subscribers, ExportFilter, ExportFormat, SubscriptionStatus,
+};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// The subscriber a data subject request is about.
+#[derive(serde::Deserialize)]
+pub struct DataSubject {
+    email: String,
+}
+
+/// Which list subscriptions to export, and how.
+#[derive(serde::Deserialize)]
+pub struct SubscriberExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    /// Slug of the list to export.
+    list: Option<String>,
+    /// Only subscriptions made at or after this time.
+    from: Option<DateTime<Utc>>,
+    /// Only subscriptions made before this time.
+    to: Option<DateTime<Utc>>,
+}
+
+/// Export the list subscriptions matching the query, as CSV or JSON Lines.
+///
+/// The response is streamed as the rows are read, so it has no length, and is cut short if the
+/// export fails part way through.
+#[tracing::instrument(name = "Admin subscriber list export", skip(query, pool))]
+pub async fn handle_admin_export_subscribers(
+    query: web::Query<SubscriberExportQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(AppError::Validation(
+                "The start of the date range must not be after its end.".into(),
+            ));
+        }
+    }
+    let list_id = match &query.list {
+        Some(slug) => Some(
+            get_list_by_slug(slug, &pool)
+                .await?
+                .ok_or(AppError::NotFound("mailing list"))?
+                .list_id,
+        ),
+        None => None,
+    };
+    let filter = ExportFilter {
+        status: query.status,
+        list_id,
+        subscribed_from: query.from,
+        subscribed_to: query.to,
+    };
+
+    let body = export_subscribers(filter, query.format, pool.get_ref().clone());
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscribers.{}",
+                query.format.file_extension()
+            ))],
+        })
+        .streaming(body))
+}
+
+/// Export everything held about the subscriber with the given address, as JSON.
+#[tracing::instrument(name = "Admin data export", skip(body, pool), fields(user_id = %*user_id))]
+pub async fn handle_admin_export_subscriber(
+    body: web::Json<DataSubject>,
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id = find_data_subject(body.into_inner(), &pool).await?;
+    let export = export_subscriber(subscriber_id, &pool)
+        .await?
+        .ok_or(AppError::NotFound("subscriber"))?;
+    Ok(HttpResponse::Ok().json(export))
+}
+
+/// Erase everything held about the subscriber with the given address, answering with the
+/// tombstone left behind.
+#[tracing::instrument(
+    name = "Admin erasure",
+    skip(body, pool, token_settings),
+    fields(user_id = %*user_id)
+)]
+pub async fn handle_admin_erase_subscriber(
+    body: web::Json<DataSubject>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id = find_data_subject(body.into_inner(), &pool).await?;
+    let tombstone = erase_subscriber(
+        subscriber_id,
+        ErasureRequester::Admin(**user_id),
+        &token_settings,
+        &pool,
+    )
+    .await?
+    .ok_or(AppError::NotFound("subscriber"))?;
+    tracing::info!(tombstone_id = %tombstone.tombstone_id, "Subscriber erased");
+    Ok(HttpResponse::Ok().json(tombstone))
+}
+
+async fn find_data_subject(subject: DataSubject, pool: &sqlx::PgPool) -> Result<Uuid, AppError> {
+    let email = ListSubscriberEmail::try_from(subject.email).map_err(|e| {
+        let mut fields = FieldErrors::default();
+        fields.add("email", e);
+        AppError::InvalidFields(fields)
+    })?;
+    find_subscriber_by_email(email.canonical(), pool)
+        .await?
+        .ok_or(AppError::NotFound("subscriber"))
+}
@@ -0,0 +1,6 @@
+//! JSON endpoints under `/api/v1`, for the SPA and mobile clients.
+mod docs;
+mod subscriptions;
+
+pub use docs::*;
+pub use subscriptions::*;
@@ -0,0 +1,60 @@
+use crate::error::{ErrorBody, FieldErrors};
+use crate::personal_data::*;
+use crate::routes::*;
+use actix_web::{HttpResponse, Responder};
+use utoipa::OpenApi;
+
+/// OpenAPI description of the public REST API.
+///
+/// The admin area is left out: it is only meant to be used through its HTML pages.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter subscriptions."),
+    paths(
+        handle_subscribe,
+        handle_form_challenge,
+        handle_confirm,
+        handle_manage_form,
+        handle_manage,
+        handle_change_email,
+        handle_export_data,
+        handle_erase_data,
+        handle_unsubscribe,
+        handle_one_click_unsubscribe,
+        handle_api_subscribe,
+        handle_liveness,
+        handle_readiness,
+    ),
+    components(schemas(
+        FormData,
+        FormChallenge,
+        PreferencesForm,
+        EmailChangeForm,
+        SubscriberExport,
+        SubscriberRecord,
+        ListSubscriptionRecord,
+        TokenRecord,
+        PendingDeliveryRecord,
+        FailedDeliveryRecord,
+        SubscriptionRequest,
+        SubscriptionResponse,
+        SubscribeOutcome,
+        HealthReport,
+        CheckReport,
+        CheckStatus,
+        ErrorBody,
+        FieldErrors,
+    )),
+    tags(
+        (name = "subscriptions", description = "Joining, managing and leaving mailing lists."),
+        (name = "health", description = "Probes for the orchestrator."),
+    )
+)]
+pub struct ApiDoc;
+
+/// Serve the OpenAPI document describing this API.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "OpenAPI document")]
+pub async fn handle_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
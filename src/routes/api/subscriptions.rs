@@ -0,0 +1,106 @@
+use crate::bot_protection::{screen_submission, FormProtection};
+use crate::configuration::{BotProtectionSettings, TokenSettings};
+use crate::domain::{EmailDomainPolicy, ListSubscriber};
+use crate::error::AppError;
+use crate::lists::DEFAULT_LIST_SLUG;
+use crate::mail::EmailClient;
+use crate::routes::{subscribe, SubscribeOutcome};
+use crate::startup::AppBaseUrl;
+use actix_web::{web, HttpResponse};
+use utoipa::ToSchema;
+
+/// Body of a JSON subscription request.
+///
+/// Missing fields are treated as empty, so that they are reported alongside the other field
+/// errors.
+#[derive(serde::Deserialize, ToSchema)]
+pub struct SubscriptionRequest {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    email: String,
+    /// Slug of the list to join. Defaults to the `default` list.
+    list: Option<String>,
+    /// Nonce from `GET /subscriptions/challenge`.
+    nonce: Option<String>,
+    /// Proof-of-work solution for the nonce, when required.
+    pow: Option<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct SubscriptionResponse {
+    outcome: SubscribeOutcome,
+}
+
+/// JSON counterpart of the subscription form.
+///
+/// New subscriptions are answered with `201 Created`, and addresses that are already known with
+/// `200 OK`; the body tells the two known cases apart. Invalid fields are listed under `fields` in
+/// the error body.
+///
+/// Requests go through the same nonce and proof-of-work checks as the form. Unlike the form, a
+/// request failing them is answered with `400`, so that clients can tell what went wrong.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    tag = "subscriptions",
+    request_body = SubscriptionRequest,
+    responses(
+        (status = 201, description = "The address was added to the list.", body = SubscriptionResponse),
+        (status = 200, description = "The address was already on the list.", body = SubscriptionResponse),
+        (status = 400, description = "The body is malformed, has invalid fields, or failed the bot protection checks.", body = ErrorBody),
+        (status = 404, description = "The list does not exist.", body = ErrorBody),
+        (status = 429, description = "Too many requests for this client or address.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+        (status = 502, description = "The confirmation email could not be sent.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Adding new subscriber through the API",
+    skip(body, db_connection, email_client, base_url, token_settings, bot_protection),
+    fields(
+        name = %body.name,
+        email = %body.email
+    )
+)]
+pub async fn handle_api_subscribe(
+    body: web::Json<SubscriptionRequest>,
+    db_connection: web::Data<sqlx::PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<AppBaseUrl>,
+    token_settings: web::Data<TokenSettings>,
+    bot_protection: web::Data<BotProtectionSettings>,
+    domain_policy: web::Data<EmailDomainPolicy>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner();
+    let protection = FormProtection {
+        // There is no field for humans to leave alone in a JSON body.
+        honeypot: "",
+        nonce: body.nonce.as_deref(),
+        proof_of_work: body.pow.as_deref(),
+    };
+    if let Some(rejection) = screen_submission(&protection, &bot_protection, &db_connection).await?
+    {
+        tracing::warn!("Rejected a subscription taken for a bot: {}", rejection);
+        return Err(AppError::Validation(format!(
+            "The request failed the bot protection checks: {}.",
+            rejection
+        )));
+    }
+    let user = ListSubscriber::parse(body.name, body.email).map_err(AppError::InvalidFields)?;
+    let outcome = subscribe(
+        user,
+        body.list.as_deref().unwrap_or(DEFAULT_LIST_SLUG),
+        &db_connection,
+        &email_client,
+        &base_url,
+        &token_settings,
+        &domain_policy,
+    )
+    .await?;
+    let mut response = match outcome {
+        SubscribeOutcome::Created => HttpResponse::Created(),
+        SubscribeOutcome::AlreadyPending | SubscribeOutcome::AlreadyConfirmed => HttpResponse::Ok(),
+    };
+    Ok(response.json(SubscriptionResponse { outcome }))
+}
@@ -1,5 +1,141 @@
-use actix_web::{HttpResponse, Responder};
+use crate::configuration::HealthSettings;
+use crate::mail::EmailClient;
+use actix_web::{web, HttpResponse, Responder};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Instant;
+use utoipa::ToSchema;
 
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok()
 }
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+/// The outcome of checking a single dependency.
+///
+/// Why a check failed is only logged, since the probes are public.
+#[derive(serde::Serialize, ToSchema)]
+pub struct CheckReport {
+    status: CheckStatus,
+    latency_ms: u64,
+}
+
+/// The overall status of the instance, along with the result of each check.
+#[derive(serde::Serialize, ToSchema)]
+pub struct HealthReport {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, CheckReport>)]
+    checks: BTreeMap<&'static str, CheckReport>,
+}
+
+/// Liveness probe: the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The process is up.", body = HealthReport))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Liveness probe")]
+pub async fn handle_liveness() -> impl Responder {
+    HttpResponse::Ok().json(HealthReport {
+        status: CheckStatus::Up,
+        checks: BTreeMap::new(),
+    })
+}
+
+/// Readiness probe: the instance can reach the services it needs to handle requests.
+///
+/// Postgres is always checked; the email provider only when enabled in `HealthSettings`. Each
+/// check reports its status and latency, and the probe answers with `503 Service Unavailable`
+/// if the database is down. The email provider is reported without affecting readiness: failed
+/// sends are already retried and dead-lettered, so a provider outage is no reason to take every
+/// instance out of rotation.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The database is reachable; the email check, if enabled, is reported but doesn't affect readiness.", body = HealthReport),
+        (status = 503, description = "The database is down.", body = HealthReport),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Readiness probe", skip_all)]
+pub async fn handle_readiness(
+    pool: web::Data<sqlx::PgPool>,
+    email_client: web::Data<EmailClient>,
+    health_settings: web::Data<HealthSettings>,
+) -> impl Responder {
+    let timeout = health_settings.timeout();
+    let database = run_check(timeout, async {
+        sqlx::query!("SELECT 1 AS one")
+            .fetch_one(pool.get_ref())
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    });
+    let email = async {
+        if health_settings.check_email_provider {
+            Some(
+                run_check(timeout, async {
+                    email_client
+                        .check_reachable()
+                        .await
+                        .map_err(|e| e.to_string())
+                })
+                .await,
+            )
+        } else {
+            None
+        }
+    };
+    let (database, email) = tokio::join!(database, email);
+
+    let status = database.status;
+    if status == CheckStatus::Down {
+        tracing::error!("Readiness probe failed");
+    }
+    let mut checks = BTreeMap::from([("database", database)]);
+    if let Some(email) = email {
+        checks.insert("email", email);
+    }
+    let report = HealthReport { status, checks };
+    match status {
+        CheckStatus::Up => HttpResponse::Ok().json(report),
+        CheckStatus::Down => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+/// Time a single dependency check, treating a check that takes longer than `timeout` as down.
+async fn run_check(
+    timeout: std::time::Duration,
+    check: impl Future<Output = Result<(), String>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(format!("Timed out after {}ms", timeout.as_millis())),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match outcome {
+        Ok(()) => CheckReport {
+            status: CheckStatus::Up,
+            latency_ms,
+        },
+        Err(error) => {
+            tracing::warn!("Dependency check failed: {}", error);
+            CheckReport {
+                status: CheckStatus::Down,
+                latency_ms,
+            }
+        }
+    }
+}
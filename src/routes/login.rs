@@ -0,0 +1,90 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials, TypedSession};
+use crate::error::AppError;
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpResponse, Responder};
+use anyhow::Context;
+use secrecy::Secret;
+
+#[derive(serde::Deserialize)]
+pub struct LoginData {
+    username: String,
+    password: Secret<String>,
+}
+
+/// Serve the admin login form.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Login form")]
+pub async fn handle_login_form() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(login_page(None))
+}
+
+/// Log an admin user in.
+///
+/// On success the session is renewed, the user's ID is stored in it, and the user is redirected
+/// to the admin dashboard. Bad credentials are answered with `401 Unauthorized` and the login
+/// form, along with an error message.
+#[tracing::instrument(
+    name = "Logging in",
+    skip(form, pool, session),
+    fields(username = %form.username, user_id)
+)]
+pub async fn handle_login(
+    form: web::Form<LoginData>,
+    pool: web::Data<sqlx::PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+    let user_id = match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => user_id,
+        Err(AuthError::InvalidCredentials) => {
+            tracing::error!("Login attempt with invalid credentials");
+            return Ok(HttpResponse::Unauthorized()
+                .content_type(ContentType::html())
+                .body(login_page(Some("Invalid username or password."))));
+        }
+        Err(AuthError::Unexpected(e)) => return Err(e.into()),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+
+    session.renew();
+    session
+        .insert_user_id(user_id)
+        .context("Failed to store the user in the session")?;
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/admin/dashboard"))
+        .finish())
+}
+
+fn login_page(error: Option<&str>) -> String {
+    let error = error
+        .map(|message| format!("<p><i>{}</i></p>", message))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+        error
+    )
+}
@@ -1,31 +1,89 @@
-use crate::domain::{ListSubscriber, ListSubscriberEmail, ListSubscriberName};
-use crate::mail::{EmailClient, EmailMessage};
+use crate::bot_protection::{screen_submission, FormProtection};
+use crate::configuration::{BotProtectionSettings, TokenSettings};
+use crate::domain::{EmailDomainPolicy, ListSubscriber, ListSubscriberEmail};
+use crate::error::{AppError, FieldErrors};
+use crate::lists::{get_list_by_slug, MailingList, DEFAULT_LIST_SLUG};
+use crate::mail::{store_dead_letter, DeadLetterKind, DeliveryFailure, EmailClient, EmailMessage};
 use crate::startup::AppBaseUrl;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpResponse};
 use chrono::Utc;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+mod challenge;
+pub use challenge::*;
+
+mod change_email;
+pub use change_email::*;
+
 mod confirmation;
 pub use confirmation::*;
 
+mod data_requests;
+pub use data_requests::*;
+
+mod manage;
+pub use manage::*;
+
 mod token;
+pub use token::hash_legacy_tokens;
+pub(crate) use token::{insert_email_change_token, rotate_token_for_id};
+
+mod unsubscribe;
+pub use unsubscribe::*;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, ToSchema)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Honeypot: hidden from humans, and must be left empty.
+    #[serde(default)]
+    website: String,
+    /// Nonce from `GET /subscriptions/challenge`.
+    nonce: Option<String>,
+    /// Proof-of-work solution for the nonce, when required.
+    pow: Option<String>,
+    /// Slug of the list to join. Defaults to the `default` list.
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for ListSubscriber {
-    type Error = String;
+    type Error = FieldErrors;
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = ListSubscriberName::try_from(form.name)?;
-        let email = ListSubscriberEmail::try_from(form.email)?;
-        Ok(Self { name, email })
+        ListSubscriber::parse(form.name, form.email)
     }
 }
 
-#[allow(clippy::async_yields_async)]
+/// What subscribing an address did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscribeOutcome {
+    /// The address is new, or had left the list; a confirmation email was sent.
+    Created,
+    /// The address was already known but never confirmed; a fresh confirmation email was sent.
+    AlreadyPending,
+    /// The address is already confirmed, so nothing was sent.
+    AlreadyConfirmed,
+}
+
+/// Subscribe to a mailing list through the HTML form.
+///
+/// A confirmation email is sent unless the address is already confirmed. Submissions failing the
+/// bot protection checks get the same response, but are dropped.
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content = FormData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The subscription was registered."),
+        (status = 400, description = "A field is missing or invalid.", body = ErrorBody),
+        (status = 404, description = "The list does not exist.", body = ErrorBody),
+        (status = 429, description = "Too many requests for this client or address.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+        (status = 502, description = "The confirmation email could not be sent.", body = ErrorBody),
+    )
+)]
 #[tracing::instrument(
     name = "Adding new subscriber",
     skip(form, db_connection),
@@ -39,131 +97,253 @@ pub async fn handle_subscribe(
     db_connection: web::Data<sqlx::PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<AppBaseUrl>,
-) -> impl Responder {
-    let user: ListSubscriber = match form.0.try_into() {
-        Ok(u) => u,
-        Err(e) => {
-            tracing::error!("Failed to parse new subscriber details: {:?}", e);
-            return HttpResponse::BadRequest();
-        }
+    token_settings: web::Data<TokenSettings>,
+    bot_protection: web::Data<BotProtectionSettings>,
+    domain_policy: web::Data<EmailDomainPolicy>,
+) -> Result<HttpResponse, AppError> {
+    let protection = FormProtection {
+        honeypot: &form.website,
+        nonce: form.nonce.as_deref(),
+        proof_of_work: form.pow.as_deref(),
     };
+    if let Some(rejection) = screen_submission(&protection, &bot_protection, &db_connection).await?
+    {
+        // Bots get no hint that they were caught.
+        tracing::warn!("Dropped a subscription taken for a bot: {}", rejection);
+        return Ok(HttpResponse::Ok().finish());
+    }
 
-    let existing_token = match get_token_for_email(&user, &db_connection).await {
-        Ok(opt) => opt,
-        Err(_) => {
-            tracing::error!("Querying DB Failed!");
-            return HttpResponse::InternalServerError();
-        }
-    };
-    let token = match existing_token {
-        Some(old_tkn) => old_tkn,
-        None => match add_new_pending_user(&user, &db_connection).await {
-            Ok(new_token) => new_token,
-            Err(e) => {
-                tracing::error!("Adding new user failed!");
-                return e;
-            }
-        },
-    };
+    let list = form.list.clone();
+    let user: ListSubscriber = form.0.try_into().map_err(AppError::InvalidFields)?;
+    subscribe(
+        user,
+        list.as_deref().unwrap_or(DEFAULT_LIST_SLUG),
+        &db_connection,
+        &email_client,
+        &base_url,
+        &token_settings,
+        &domain_policy,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    match send_confirmation_email(email_client.get_ref(), user, token, base_url.get_ref()).await {
-        Ok(_) => {
-            tracing::info!("Email sent");
+/// Add a subscriber to the list with the given slug, sending them a confirmation email unless
+/// they are already confirmed on that list. Addresses whose domain isn't accepted by the policy
+/// are turned away first.
+///
+/// This is shared by the form and the JSON endpoints.
+pub(crate) async fn subscribe(
+    user: ListSubscriber,
+    list_slug: &str,
+    db_connection: &sqlx::PgPool,
+    email_client: &EmailClient,
+    base_url: &AppBaseUrl,
+    token_settings: &TokenSettings,
+    domain_policy: &EmailDomainPolicy,
+) -> Result<SubscribeOutcome, AppError> {
+    let list = get_list_by_slug(list_slug, db_connection)
+        .await?
+        .ok_or(AppError::NotFound("mailing list"))?;
+    domain_policy
+        .check(&user.email)
+        .map_err(AppError::EmailDomainRejected)?;
+    // Tokens are only stored hashed, so an existing user always gets a freshly issued token.
+    let existing = get_existing_subscription(&user, list.list_id, db_connection).await?;
+    let (outcome, subscriber_id, token) = match existing {
+        Some((id, Some(status))) => match status.as_str() {
+            "confirmed" => return Ok(SubscribeOutcome::AlreadyConfirmed),
+            "pending" => (
+                SubscribeOutcome::AlreadyPending,
+                id,
+                rotate_user_token(id, list.list_id, token_settings, db_connection).await?,
+            ),
+            "unsubscribed" => (
+                SubscribeOutcome::Created,
+                id,
+                resubscribe_user(id, list.list_id, token_settings, db_connection).await?,
+            ),
+            other => return Err(anyhow::anyhow!("Unknown subscription status: {}", other).into()),
+        },
+        Some((id, None)) => {
+            let (id, token) = add_pending_subscription(
+                &user,
+                Some(id),
+                list.list_id,
+                token_settings,
+                db_connection,
+            )
+            .await?;
+            (SubscribeOutcome::Created, id, token)
         }
-        Err(e) => {
-            tracing::error!("Failed to send email. {:?}", e);
-            return HttpResponse::InternalServerError();
+        None => {
+            let (id, token) =
+                add_pending_subscription(&user, None, list.list_id, token_settings, db_connection)
+                    .await?;
+            (SubscribeOutcome::Created, id, token)
         }
-    }
+    };
 
-    HttpResponse::Ok()
+    send_confirmation_email(
+        email_client,
+        db_connection,
+        user,
+        subscriber_id,
+        &list,
+        token,
+        base_url,
+    )
+    .await
+    .map_err(AppError::Email)?;
+    tracing::info!("Email sent");
+    Ok(outcome)
 }
 
 /// Send a confirmation email
-#[tracing::instrument(name = "Sending confirmation email")]
+#[tracing::instrument(name = "Sending confirmation email", skip(db_connection))]
 async fn send_confirmation_email(
     email_client: &EmailClient,
+    db_connection: &sqlx::PgPool,
     user: ListSubscriber,
+    subscriber_id: Uuid,
+    list: &MailingList,
     token: String,
     base_url: &AppBaseUrl,
-) -> Result<reqwest::Response, reqwest::Error> {
+) -> Result<(), DeliveryFailure> {
+    let message = confirmation_message(user.email, &list.name, &token, base_url);
+    let kind = DeadLetterKind::Confirmation {
+        subscriber_id,
+        list_id: list.list_id,
+    };
+    deliver_email(email_client, db_connection, message, &kind).await
+}
+
+/// The email carrying the link that confirms a subscription to the named list.
+///
+/// This is shared with the worker sending confirmations for imported subscribers.
+pub(crate) fn confirmation_message(
+    recipient: ListSubscriberEmail,
+    list_name: &str,
+    token: &str,
+    base_url: &AppBaseUrl,
+) -> EmailMessage {
     let confirm_link = format!("{}/subscriptions/confirm?token={}", base_url.0, token);
-    let message = EmailMessage {
-        recipient: user.email,
+    EmailMessage {
+        recipient,
         subject: "Derp".into(),
-        body_text: format!("Welcome to my mailing list. Link: {}", confirm_link),
-        body_html: format!("Welcome to my list <a href={}>Link</a>", confirm_link),
-    };
+        body_text: format!("Welcome to {}. Link: {}", list_name, confirm_link),
+        body_html: format!(
+            "Welcome to {} <a href={}>Link</a>",
+            htmlescape::encode_minimal(list_name),
+            confirm_link
+        ),
+        list_unsubscribe: None,
+    }
+}
 
-    email_client.send_mail(message).await
+/// Send an email outside of a newsletter issue.
+///
+/// Transient failures are retried. If the email still can't be delivered, it is kept in the
+/// dead-letter table as the given kind.
+async fn deliver_email(
+    email_client: &EmailClient,
+    db_connection: &sqlx::PgPool,
+    message: EmailMessage,
+    kind: &DeadLetterKind,
+) -> Result<(), DeliveryFailure> {
+    let result = email_client.send_mail_with_retry(message.clone()).await;
+    if let Err(failure) = &result {
+        if let Err(e) = store_dead_letter(db_connection, &message, kind, failure).await {
+            tracing::error!("Failed to store dead letter: {:?}", e);
+        }
+    }
+    result
 }
 
-/// Attempt to find an existing user.
-async fn get_token_for_email(
+/// Attempt to find an existing user, returning their ID and the status of their subscription to
+/// the list, if they have one.
+async fn get_existing_subscription(
     user: &ListSubscriber,
+    list_id: Uuid,
     db_connection: &sqlx::PgPool,
-) -> Result<Option<String>, sqlx::Error> {
+) -> Result<Option<(Uuid, Option<String>)>, sqlx::Error> {
     let response = sqlx::query!(
         r#"
-        SELECT id FROM subscriptions
-        WHERE email = $1
+        SELECT s.id, l.status AS "status?"
+        FROM subscriptions s
+        LEFT JOIN list_subscriptions l ON l.subscriber_id = s.id AND l.list_id = $2
+        WHERE s.email_canonical = $1
         "#,
-        user.email.as_ref(),
+        user.email.canonical(),
+        list_id,
     )
     .fetch_optional(db_connection)
     .await?;
-    if let Some(id) = response.map(|a| a.id) {
-        Ok(token::get_token_for_id(id, db_connection).await?)
-    } else {
-        Ok(None)
-    }
+    Ok(response.map(|a| (a.id, a.status)))
 }
 
-/// Add a new user, registering a new user ID and token within the database.
-async fn add_new_pending_user(
+/// Add a pending subscription to the list, registering the user first unless their ID is already
+/// known, then issue a token for it. Returns the subscriber's ID along with the token.
+async fn add_pending_subscription(
     user: &ListSubscriber,
+    subscriber_id: Option<Uuid>,
+    list_id: Uuid,
+    token_settings: &TokenSettings,
     db_connection: &sqlx::PgPool,
-) -> Result<String, actix_web::HttpResponseBuilder> {
-    let mut txn = match db_connection.begin().await {
-        Ok(txn) => txn,
-        Err(_) => {
-            tracing::error!("Failed to start PG Transaction");
-            return Err(HttpResponse::InternalServerError());
-        }
-    };
-
-    let subscriber_id = match db_insert_user(user, &mut txn).await {
-        Ok(id) => {
-            tracing::info!("Database modification successful!");
-            id
-        }
-        Err(e) => {
-            tracing::error!("Failed to execute query: {:?}", e);
-            return Err(HttpResponse::InternalServerError());
-        }
-    };
-
-    let token = match token::insert_token_for_id(subscriber_id, &mut txn).await {
-        Ok(token) => {
-            tracing::info!("Token Generation successful!");
-            token
-        }
-        Err(e) => {
-            tracing::error!("Failed to execute query: {:?}", e);
-            return Err(HttpResponse::InternalServerError());
-        }
+) -> Result<(Uuid, String), sqlx::Error> {
+    let mut txn = db_connection.begin().await?;
+    let subscriber_id = match subscriber_id {
+        Some(id) => id,
+        None => db_insert_user(user, &mut txn).await?,
     };
+    db_insert_list_subscription(subscriber_id, list_id, &mut txn).await?;
+    let token =
+        token::insert_token_for_id(subscriber_id, list_id, token_settings, &mut txn).await?;
+    txn.commit().await?;
+    Ok((subscriber_id, token))
+}
 
-    if txn.commit().await.is_err() {
-        tracing::error!("Transaction failed to commit!!");
-        return Err(HttpResponse::InternalServerError());
-    }
+/// Replace an existing user's tokens for the list with a fresh one.
+async fn rotate_user_token(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    token_settings: &TokenSettings,
+    db_connection: &sqlx::PgPool,
+) -> Result<String, sqlx::Error> {
+    let mut txn = db_connection.begin().await?;
+    let token =
+        token::rotate_token_for_id(subscriber_id, list_id, token_settings, &mut txn).await?;
+    txn.commit().await?;
+    Ok(token)
+}
 
+/// Make a subscription the user left pending again, then issue a fresh token for it.
+async fn resubscribe_user(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    token_settings: &TokenSettings,
+    db_connection: &sqlx::PgPool,
+) -> Result<String, sqlx::Error> {
+    let mut txn = db_connection.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'pending', subscribed_at = $3, unsubscribed_at = NULL
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id,
+        Utc::now()
+    )
+    .execute(&mut txn)
+    .await?;
+    let token =
+        token::rotate_token_for_id(subscriber_id, list_id, token_settings, &mut txn).await?;
+    txn.commit().await?;
     Ok(token)
 }
 
 /// Insert a user into the database
-/// By default, the user is inserted as pending confirmation.
 #[tracing::instrument(name = "Adding user to database", skip(subscriber, db_connection))]
 async fn db_insert_user(
     subscriber: &ListSubscriber,
@@ -173,11 +353,12 @@ async fn db_insert_user(
     // Query!
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending')
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+        VALUES ($1, $2, $3, $4, $5)
         "#,
         subscriber_id,
         subscriber.email.as_ref(),
+        subscriber.email.canonical(),
         subscriber.name.as_ref(),
         Utc::now()
     )
@@ -185,3 +366,24 @@ async fn db_insert_user(
     .await?;
     Ok(subscriber_id)
 }
+
+/// Subscribe a user to a list. The subscription is pending until it is confirmed.
+#[tracing::instrument(name = "Adding list subscription to database", skip(db_connection))]
+async fn db_insert_list_subscription(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    db_connection: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending', $3)
+        "#,
+        list_id,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(db_connection)
+    .await?;
+    Ok(())
+}
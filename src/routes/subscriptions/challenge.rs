@@ -0,0 +1,34 @@
+use crate::bot_protection::issue_nonce;
+use crate::configuration::BotProtectionSettings;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use utoipa::ToSchema;
+
+/// What a client needs to fill in the bot protection fields of the subscription form.
+#[derive(serde::Serialize, ToSchema)]
+pub struct FormChallenge {
+    /// Signed nonce to submit in the `nonce` field.
+    nonce: String,
+    /// Leading zero bits required of SHA-256(`<nonce>:<pow>`); `0` means no proof of work is
+    /// needed.
+    pow_difficulty: u8,
+}
+
+/// Issue a nonce for the subscription form.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/challenge",
+    tag = "subscriptions",
+    responses((status = 200, description = "A fresh form nonce.", body = FormChallenge))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Issuing form challenge", skip_all)]
+pub async fn handle_form_challenge(settings: web::Data<BotProtectionSettings>) -> impl Responder {
+    HttpResponse::Ok()
+        // Every form needs a nonce of its own.
+        .insert_header(("Cache-Control", "no-store"))
+        .json(FormChallenge {
+            nonce: issue_nonce(&settings, Utc::now()),
+            pow_difficulty: settings.pow_difficulty,
+        })
+}
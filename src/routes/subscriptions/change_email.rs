@@ -0,0 +1,258 @@
+use super::manage::{get_managed_subscriber, manage_page};
+use super::{deliver_email, token, Token};
+use crate::configuration::TokenSettings;
+use crate::domain::{EmailDomainPolicy, ListSubscriberEmail};
+use crate::error::AppError;
+use crate::mail::{DeadLetterKind, EmailClient, EmailMessage};
+use crate::startup::AppBaseUrl;
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpResponse};
+use anyhow::anyhow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct EmailChangeForm {
+    /// The address to move the subscription to.
+    email: String,
+}
+
+/// Start moving the subscription of the subscriber holding the management token to a new
+/// address.
+///
+/// A confirmation link is sent to the new address, and the subscription only moves once it is
+/// followed; the old address is then told about the change. Invalid addresses, addresses that may
+/// not subscribe, and the subscriber's current address are answered with `400 Bad Request` and
+/// the preference page, along with an error message.
+///
+/// Addresses held by other subscribers get the same answer as free ones, so that the form can't
+/// be used to find out who is subscribed. Following their link then fails with `409 Conflict`.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/manage/email",
+    tag = "subscriptions",
+    params(Token),
+    request_body(content = EmailChangeForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "A confirmation link was sent to the new address."),
+        (status = 400, description = "The address can't be used.", content_type = "text/html"),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 429, description = "Too many requests for this client or address.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+        (status = 502, description = "The confirmation email could not be sent.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Requesting an email change", skip_all)]
+pub async fn handle_change_email(
+    query: web::Query<Token>,
+    form: web::Form<EmailChangeForm>,
+    pool: web::Data<sqlx::PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<AppBaseUrl>,
+    token_settings: web::Data<TokenSettings>,
+    domain_policy: web::Data<EmailDomainPolicy>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id =
+        token::get_subscriber_for_management_token(&query.token, &token_settings, &pool)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+    let new_email =
+        match check_new_email(form.0.email, subscriber_id, &domain_policy, &pool).await? {
+            Ok(email) => email,
+            Err(error) => {
+                let subscriber = get_managed_subscriber(subscriber_id, &pool).await?;
+                return Ok(HttpResponse::BadRequest()
+                    .content_type(ContentType::html())
+                    .body(manage_page(&subscriber, &query.token, &[error])));
+            }
+        };
+
+    let mut txn = pool.begin().await?;
+    let change_token = token::insert_email_change_token(
+        subscriber_id,
+        new_email.as_ref(),
+        &token_settings,
+        &mut txn,
+    )
+    .await?;
+    txn.commit().await?;
+    let message = email_change_message(new_email, &change_token, &base_url);
+    deliver_email(
+        &email_client,
+        &pool,
+        message,
+        &DeadLetterKind::EmailChange { subscriber_id },
+    )
+    .await
+    .map_err(AppError::Email)?;
+    tracing::info!("Email change confirmation sent");
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((
+            LOCATION,
+            format!("/subscriptions/manage?token={}", query.token),
+        ))
+        .finish())
+}
+
+/// Check that the subscription may move to the given address, returning a message for the
+/// subscriber if it may not.
+///
+/// Whether another subscriber holds the address is deliberately left to the confirmation.
+async fn check_new_email(
+    email: String,
+    subscriber_id: Uuid,
+    domain_policy: &EmailDomainPolicy,
+    pool: &sqlx::PgPool,
+) -> Result<Result<ListSubscriberEmail, String>, sqlx::Error> {
+    let email = match ListSubscriberEmail::try_from(email) {
+        Ok(email) => email,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Err(rejection) = domain_policy.check(&email) {
+        return Ok(Err(rejection.to_string()));
+    }
+    let current = sqlx::query!(
+        r#"
+        SELECT email_canonical FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?
+    .email_canonical;
+    if current == email.canonical() {
+        return Ok(Err("This is already your address.".into()));
+    }
+    Ok(Ok(email))
+}
+
+/// The email carrying the link that confirms a change of address, sent to the new address.
+///
+/// This is shared with the replay of dead letters.
+pub(crate) fn email_change_message(
+    new_email: ListSubscriberEmail,
+    token: &str,
+    base_url: &AppBaseUrl,
+) -> EmailMessage {
+    let confirm_link = format!("{}/subscriptions/confirm?token={}", base_url.0, token);
+    EmailMessage {
+        recipient: new_email,
+        subject: "Confirm your new address".into(),
+        body_text: format!(
+            "Follow this link to move your subscription to this address. Link: {}",
+            confirm_link
+        ),
+        body_html: format!(
+            "Follow this link to move your subscription to this address <a href={}>Link</a>",
+            confirm_link
+        ),
+        list_unsubscribe: None,
+    }
+}
+
+/// Move the subscriber to the address an email change token was sent to, then let the old
+/// address know.
+///
+/// The change fails with `409 Conflict` if another subscriber holds the new address. Failing to
+/// notify the old address doesn't undo the change; the notification is kept as a dead letter
+/// instead.
+pub(super) async fn confirm_email_change(
+    subscriber_id: Uuid,
+    new_email: String,
+    pool: &sqlx::PgPool,
+    email_client: &EmailClient,
+) -> Result<HttpResponse, AppError> {
+    let new_email = ListSubscriberEmail::try_from(new_email)
+        .map_err(|e| anyhow!("An email change token holds an invalid address: {}", e))?;
+
+    let mut txn = pool.begin().await?;
+    let old_email = sqlx::query!(
+        r#"
+        SELECT email FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&mut txn)
+    .await?
+    .email;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET email = $2, email_canonical = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_email.as_ref(),
+        new_email.canonical()
+    )
+    .execute(&mut txn)
+    .await;
+    match updated {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505") => {
+            return Err(AppError::Conflict(
+                "The new address is already subscribed.".into(),
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    }
+    // Change tokens are single use.
+    sqlx::query!(
+        r#"
+        DELETE FROM tokens
+        WHERE subscriber_id = $1 AND new_email IS NOT NULL
+        "#,
+        subscriber_id
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+    tracing::info!("Subscriber email changed");
+
+    match ListSubscriberEmail::try_from(old_email) {
+        Ok(old_email) => {
+            let message = EmailMessage {
+                recipient: old_email,
+                subject: "Your subscription has moved".into(),
+                body_text: format!(
+                    "Your subscription now goes to {}. If you didn't ask for this, reply to this email.",
+                    new_email.as_ref()
+                ),
+                body_html: format!(
+                    "Your subscription now goes to {}. If you didn't ask for this, reply to this email.",
+                    htmlescape::encode_minimal(new_email.as_ref())
+                ),
+                list_unsubscribe: None,
+            };
+            // The notice names the new address, so it belongs to the subscriber even though it
+            // goes to the old one.
+            let kind = DeadLetterKind::Notice {
+                subscriber_id: Some(subscriber_id),
+            };
+            if let Err(failure) = deliver_email(email_client, pool, message, &kind).await {
+                tracing::error!("Failed to notify the old address: {}", failure);
+            }
+        }
+        Err(e) => tracing::error!("Not notifying an invalid old address: {}", e),
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Address changed</title>
+</head>
+<body>
+    <p>Your subscription now goes to {}.</p>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(new_email.as_ref())
+        )))
+}
@@ -1,8 +1,20 @@
-use super::token;
-use actix_web::{web, HttpResponse, Responder};
+use super::change_email::confirm_email_change;
+use super::deliver_email;
+use super::manage::management_link;
+use super::token::{self, TokenPurpose};
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberEmail;
+use crate::error::AppError;
+use crate::mail::{DeadLetterKind, EmailClient, EmailMessage};
+use crate::startup::AppBaseUrl;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use utoipa::IntoParams;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Token {
+    /// The subscription token from the link in the subscriber's email.
     pub token: String,
 }
 
@@ -10,49 +22,185 @@ pub struct Token {
 ///
 /// This endpoint uses the user's subscription token to validate that the user actually controls
 /// the registered e-mail. It handles database management as well as user feedback for the
-/// confirmation.
-#[allow(clippy::async_yields_async)]
+/// confirmation. Expired tokens are answered with `410 Gone` so that the user knows to request a
+/// fresh confirmation email.
+///
+/// Subscription tokens are issued per list, so only the subscription to the token's list is
+/// confirmed. The subscriber is then sent the link to their preference page, which the answering
+/// page also shows. The token is kept until it expires but only confirms once: following it again,
+/// as people and mail scanners do, is answered without confirming or welcoming the subscriber
+/// again. Tokens sent for a change of address move the subscriber to the new address instead.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The subscription is confirmed, or was by an earlier click.", content_type = "text/html"),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 409, description = "The new address belongs to another subscriber.", body = ErrorBody),
+        (status = 410, description = "The token has expired.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
 #[tracing::instrument(name = "Subscriber Confirmation endpoint", skip(query))]
 pub async fn handle_confirm(
     query: web::Query<Token>,
     pool: web::Data<sqlx::PgPool>,
-) -> impl Responder {
-    let id = match token::get_id_for_token(query.token.clone(), &pool).await {
-        Ok(id) => id,
-        Err(_) => {
-            tracing::error!("Getting ID for token failed!");
-            return HttpResponse::InternalServerError();
+    token_settings: web::Data<TokenSettings>,
+    base_url: web::Data<AppBaseUrl>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, AppError> {
+    let record = match token::get_id_for_token(query.token.clone(), &token_settings, &pool).await? {
+        Some(record) if record.is_expired() => return Err(AppError::ExpiredToken),
+        Some(record) => record,
+        None => return Err(AppError::InvalidToken),
+    };
+    let list_id = match record.purpose {
+        TokenPurpose::Subscription { list_id } => list_id,
+        TokenPurpose::EmailChange { new_email } => {
+            return confirm_email_change(record.subscriber_id, new_email, &pool, &email_client)
+                .await
         }
     };
 
-    let id = match id {
-        Some(id) => id,
+    let confirmed = match confirm_id(
+        &query.token,
+        record.subscriber_id,
+        list_id,
+        &token_settings,
+        &pool,
+    )
+    .await?
+    {
+        Some(confirmed) => confirmed,
         None => {
-            tracing::error!("No such token {} found", query.token);
-            return HttpResponse::Unauthorized();
+            tracing::info!("Confirmation link followed again");
+            return Ok(already_confirmed_page());
         }
     };
+    tracing::info!("User confirmation successful!");
 
-    match confirm_id(id, &pool).await {
-        Ok(_) => {
-            tracing::info!("User confirmation successful!");
-            HttpResponse::Ok()
-        }
-        Err(_) => {
-            tracing::error!("User confirmation query failed!");
-            HttpResponse::InternalServerError()
+    let manage_link =
+        management_link(record.subscriber_id, &token_settings, &base_url, &**pool).await?;
+    // The subscription stands even if the welcome can't be sent; it is kept as a dead letter.
+    match ListSubscriberEmail::try_from(confirmed.email) {
+        Ok(recipient) => {
+            let message = welcome_message(recipient, &confirmed.list_name, &manage_link);
+            let kind = DeadLetterKind::Welcome {
+                subscriber_id: record.subscriber_id,
+                list_id,
+            };
+            if let Err(failure) = deliver_email(&email_client, &pool, message, &kind).await {
+                tracing::error!("Failed to send the welcome email: {}", failure);
+            }
         }
+        Err(e) => tracing::error!("Not welcoming an invalid address: {}", e),
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription confirmed</title>
+</head>
+<body>
+    <p>Your subscription is confirmed. Thank you!</p>
+    <p>Keep this link to <a href="{}">manage your subscription</a>.</p>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&manage_link)
+        )))
+}
+
+/// The page answering a confirmation link that was already used. It doesn't show the management
+/// link, so that the confirmation link can't be used to look it up.
+fn already_confirmed_page() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription confirmed</title>
+</head>
+<body>
+    <p>This link was already used to confirm your subscription.</p>
+    <p>The link to manage your subscription is in the welcome email.</p>
+</body>
+</html>"#,
+    )
+}
+
+/// The email sent once a subscription to the named list is confirmed, carrying the link to the
+/// subscriber's preference page.
+///
+/// This is shared with the replay of dead letters.
+pub(crate) fn welcome_message(
+    recipient: ListSubscriberEmail,
+    list_name: &str,
+    manage_link: &str,
+) -> EmailMessage {
+    EmailMessage {
+        recipient,
+        subject: "Your subscription is confirmed".into(),
+        body_text: format!(
+            "Your subscription to {} is confirmed. Keep this link to manage your subscription: {}",
+            list_name, manage_link
+        ),
+        body_html: format!(
+            "Your subscription to {} is confirmed. Keep this link to \
+            <a href=\"{}\">manage your subscription</a>.",
+            htmlescape::encode_minimal(list_name),
+            htmlescape::encode_minimal(manage_link)
+        ),
+        list_unsubscribe: None,
     }
 }
 
-async fn confirm_id(id: uuid::Uuid, pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+/// The subscriber and list of a confirmed subscription.
+struct ConfirmedSubscription {
+    email: String,
+    list_name: String,
+}
+
+/// Confirm the subscriber's subscription to the list the token was issued for, marking the token
+/// used. Return `None` without changing the subscription if the token was used before.
+async fn confirm_id(
+    token: &str,
+    id: uuid::Uuid,
+    list_id: uuid::Uuid,
+    token_settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<Option<ConfirmedSubscription>, sqlx::Error> {
+    let mut txn = pool.begin().await?;
+    if !token::use_token(token, token_settings, &mut txn).await? {
+        return Ok(None);
+    }
     sqlx::query!(
         r#"
-        UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+        UPDATE list_subscriptions SET status = 'confirmed', unsubscribed_at = NULL
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        id,
+        list_id,
+    )
+    .execute(&mut txn)
+    .await?;
+    let confirmed = sqlx::query_as!(
+        ConfirmedSubscription,
+        r#"
+        SELECT s.email, l.name AS list_name
+        FROM subscriptions s, lists l
+        WHERE s.id = $1 AND l.list_id = $2
         "#,
         id,
+        list_id,
     )
-    .execute(pool)
+    .fetch_one(&mut txn)
     .await?;
-    Ok(())
+    txn.commit().await?;
+    Ok(Some(confirmed))
 }
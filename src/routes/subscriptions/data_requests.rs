@@ -0,0 +1,89 @@
+use super::{token, Token};
+use crate::configuration::TokenSettings;
+use crate::error::AppError;
+use crate::personal_data::{erase_subscriber, export_subscriber, ErasureRequester};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+
+/// Download everything held about the subscriber holding the management token, as JSON.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/manage/export",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The subscriber's data.", body = SubscriberExport),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Subscriber data export", skip_all)]
+pub async fn handle_export_data(
+    query: web::Query<Token>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id =
+        token::get_subscriber_for_management_token(&query.token, &token_settings, &pool)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+    let export = export_subscriber(subscriber_id, &pool)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(export))
+}
+
+/// Erase everything held about the subscriber holding the management token.
+///
+/// The subscriber leaves every list, and their management link stops working. Only a tombstone
+/// is kept, recording that the erasure happened.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/manage/erase",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The subscriber's data was erased.", content_type = "text/html"),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Subscriber erasure", skip_all)]
+pub async fn handle_erase_data(
+    query: web::Query<Token>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id =
+        token::get_subscriber_for_management_token(&query.token, &token_settings, &pool)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+    let tombstone = erase_subscriber(
+        subscriber_id,
+        ErasureRequester::Subscriber,
+        &token_settings,
+        &pool,
+    )
+    .await?
+    .ok_or(AppError::InvalidToken)?;
+    tracing::info!(tombstone_id = %tombstone.tombstone_id, "Subscriber erased");
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Data erased</title>
+</head>
+<body>
+    <p>Everything we held about you has been erased.</p>
+</body>
+</html>"#,
+    ))
+}
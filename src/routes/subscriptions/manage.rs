@@ -0,0 +1,498 @@
+use super::token;
+use super::Token;
+use crate::configuration::TokenSettings;
+use crate::domain::ListSubscriberName;
+use crate::error::AppError;
+use crate::startup::AppBaseUrl;
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The longest a subscriber may pause delivery for.
+const MAX_PAUSE_WEEKS: u32 = 52;
+
+/// The preferences submitted from the management page.
+#[derive(Debug, Default, ToSchema)]
+pub struct PreferencesForm {
+    /// The name newsletters are addressed to.
+    name: String,
+    /// Slug of a list to receive. Repeat the field once per list; confirmed lists left out are
+    /// unsubscribed from.
+    lists: Vec<String>,
+    /// Weeks to pause delivery for. `0` resumes delivery, and an empty value keeps the current
+    /// setting.
+    pause_weeks: Option<String>,
+    /// `unsubscribe` leaves every list and ignores the other fields.
+    action: Option<String>,
+}
+
+impl From<Vec<(String, String)>> for PreferencesForm {
+    fn from(fields: Vec<(String, String)>) -> Self {
+        let mut form = Self::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => form.name = value,
+                "lists" => form.lists.push(value),
+                "pause_weeks" => form.pause_weeks = Some(value),
+                "action" => form.action = Some(value),
+                _ => {}
+            }
+        }
+        form
+    }
+}
+
+/// A validated change of preferences.
+struct PreferenceUpdate {
+    name: ListSubscriberName,
+    lists: Vec<String>,
+    /// `None` keeps the current pause, `Some(None)` resumes delivery.
+    paused_until: Option<Option<DateTime<Utc>>>,
+}
+
+impl PreferencesForm {
+    /// Check the form against the lists on offer, collecting a message for each invalid field.
+    fn validate(
+        self,
+        lists: &[ListChoice],
+        now: DateTime<Utc>,
+    ) -> Result<PreferenceUpdate, Vec<String>> {
+        let mut errors = Vec::new();
+        let name = ListSubscriberName::try_from(self.name)
+            .map_err(|e| errors.push(e))
+            .ok();
+        for slug in &self.lists {
+            if !lists.iter().any(|list| &list.slug == slug) {
+                errors.push(format!("There is no list named {}.", slug));
+            }
+        }
+        let paused_until = match self.pause_weeks.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(weeks) => match weeks.parse::<u32>() {
+                Ok(0) => Some(None),
+                Ok(weeks) if weeks <= MAX_PAUSE_WEEKS => {
+                    Some(Some(now + Duration::weeks(weeks.into())))
+                }
+                _ => {
+                    errors.push(format!(
+                        "Delivery can be paused for up to {} weeks.",
+                        MAX_PAUSE_WEEKS
+                    ));
+                    None
+                }
+            },
+        };
+        match name {
+            Some(name) if errors.is_empty() => Ok(PreferenceUpdate {
+                name,
+                lists: self.lists,
+                paused_until,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// A subscriber as shown on their management page.
+pub(super) struct ManagedSubscriber {
+    email: String,
+    name: String,
+    paused_until: Option<DateTime<Utc>>,
+    /// The address the subscriber asked to move to, until they confirm it.
+    pending_email: Option<String>,
+    lists: Vec<ListChoice>,
+}
+
+/// A list on offer, along with the status of the subscriber's subscription to it, if any.
+struct ListChoice {
+    slug: String,
+    name: String,
+    status: Option<String>,
+}
+
+/// Show the preference page of the subscriber holding the management token.
+///
+/// The link to this page is handed out when a subscription is confirmed.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/manage",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The preference page.", content_type = "text/html"),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Subscriber preference page", skip(query, pool, token_settings))]
+pub async fn handle_manage_form(
+    query: web::Query<Token>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id =
+        token::get_subscriber_for_management_token(&query.token, &token_settings, &pool)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+    let subscriber = get_managed_subscriber(subscriber_id, &pool).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(manage_page(&subscriber, &query.token, &[])))
+}
+
+/// Save the preferences of the subscriber holding the management token.
+///
+/// The subscriber may rename themselves, pick the lists they receive, and pause delivery. Lists
+/// picked here are confirmed straight away, since the token already proves that the subscriber
+/// controls the address. Saving redirects back to the preference page; invalid fields are
+/// answered with `400 Bad Request` and the page, along with an error message for each field.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/manage",
+    tag = "subscriptions",
+    params(Token),
+    request_body(content = PreferencesForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "The preferences were saved."),
+        (status = 400, description = "A field is invalid.", content_type = "text/html"),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Saving subscriber preferences",
+    skip(query, form, pool, token_settings)
+)]
+pub async fn handle_manage(
+    query: web::Query<Token>,
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_id =
+        token::get_subscriber_for_management_token(&query.token, &token_settings, &pool)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+    let form = PreferencesForm::from(form.into_inner());
+
+    if form.action.as_deref() == Some("unsubscribe") {
+        unsubscribe_from_all_lists(subscriber_id, &pool).await?;
+        tracing::info!("Subscriber left every list");
+    } else {
+        let subscriber = get_managed_subscriber(subscriber_id, &pool).await?;
+        match form.validate(&subscriber.lists, Utc::now()) {
+            Ok(update) => save_preferences(subscriber_id, update, &pool).await?,
+            Err(errors) => {
+                return Ok(HttpResponse::BadRequest()
+                    .content_type(ContentType::html())
+                    .body(manage_page(&subscriber, &query.token, &errors)))
+            }
+        }
+        tracing::info!("Subscriber preferences saved");
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((
+            LOCATION,
+            format!("/subscriptions/manage?token={}", query.token),
+        ))
+        .finish())
+}
+
+/// Build the link to the subscriber's preference page. The link is the same every time, so it
+/// can go in every email sent to them.
+pub(crate) async fn management_link<'e, E>(
+    subscriber_id: Uuid,
+    token_settings: &TokenSettings,
+    base_url: &AppBaseUrl,
+    executor: E,
+) -> Result<String, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let token = token::management_token(subscriber_id, token_settings, executor).await?;
+    Ok(format!(
+        "{}/subscriptions/manage?token={}",
+        base_url.0, token
+    ))
+}
+
+pub(super) async fn get_managed_subscriber(
+    subscriber_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<ManagedSubscriber, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT email, name, paused_until FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let lists = sqlx::query_as!(
+        ListChoice,
+        r#"
+        SELECT l.slug, l.name, s.status AS "status?"
+        FROM lists l
+        LEFT JOIN list_subscriptions s ON s.list_id = l.list_id AND s.subscriber_id = $1
+        ORDER BY l.created_at, l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let pending_email = sqlx::query!(
+        r#"
+        SELECT new_email AS "new_email!" FROM tokens
+        WHERE subscriber_id = $1 AND new_email IS NOT NULL AND expires_at > now()
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.new_email);
+    Ok(ManagedSubscriber {
+        email: subscriber.email,
+        name: subscriber.name,
+        paused_until: subscriber.paused_until,
+        pending_email,
+        lists,
+    })
+}
+
+/// Apply a change of preferences. Lists that were confirmed but aren't picked anymore are
+/// unsubscribed from; pending subscriptions that weren't picked are left alone.
+async fn save_preferences(
+    subscriber_id: Uuid,
+    update: PreferenceUpdate,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let mut txn = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET name = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        update.name.as_ref()
+    )
+    .execute(&mut txn)
+    .await?;
+    if let Some(paused_until) = update.paused_until {
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions SET paused_until = $2
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            paused_until
+        )
+        .execute(&mut txn)
+        .await?;
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT list_id, $1, 'confirmed', $3 FROM lists
+        WHERE slug = ANY($2)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = 'confirmed', unsubscribed_at = NULL
+        "#,
+        subscriber_id,
+        &update.lists[..],
+        now
+    )
+    .execute(&mut txn)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = COALESCE(unsubscribed_at, $3)
+        WHERE subscriber_id = $1
+            AND status = 'confirmed'
+            AND list_id NOT IN (SELECT list_id FROM lists WHERE slug = ANY($2))
+        "#,
+        subscriber_id,
+        &update.lists[..],
+        now
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
+
+/// Unsubscribe from every list, including the subscriptions still awaiting confirmation.
+async fn unsubscribe_from_all_lists(
+    subscriber_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = COALESCE(unsubscribed_at, $2)
+        WHERE subscriber_id = $1 AND status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub(super) fn manage_page(
+    subscriber: &ManagedSubscriber,
+    token: &str,
+    errors: &[String],
+) -> String {
+    let errors: String = errors
+        .iter()
+        .map(|message| format!("<p><i>{}</i></p>", htmlescape::encode_minimal(message)))
+        .collect();
+    let lists: String = subscriber
+        .lists
+        .iter()
+        .map(|list| {
+            let (checked, note) = match list.status.as_deref() {
+                Some("confirmed") => (" checked", ""),
+                Some("pending") => ("", " (awaiting confirmation)"),
+                _ => ("", ""),
+            };
+            format!(
+                r#"
+        <label><input type="checkbox" name="lists" value="{}"{}> {}{}</label><br>"#,
+                htmlescape::encode_attribute(&list.slug),
+                checked,
+                htmlescape::encode_minimal(&list.name),
+                note
+            )
+        })
+        .collect();
+    let pause = match subscriber.paused_until {
+        Some(until) if until > Utc::now() => format!(
+            "<p>Delivery is paused until {}.</p>",
+            until.format("%B %-d, %Y")
+        ),
+        _ => String::new(),
+    };
+    let pending_email = match &subscriber.pending_email {
+        Some(email) => format!(
+            "<p>Follow the link sent to {} to start using that address.</p>",
+            htmlescape::encode_minimal(email)
+        ),
+        None => String::new(),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Manage your subscription</title>
+</head>
+<body>
+    <p>Subscription preferences for {}</p>
+    {}
+    {}
+    {}
+    <form action="/subscriptions/manage?token={}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{}">
+        </label>
+        <fieldset>
+        <legend>Lists</legend>{}
+        </fieldset>
+        <label>Pause delivery
+            <select name="pause_weeks">
+                <option value="">Keep current setting</option>
+                <option value="0">Resume now</option>
+                <option value="1">For 1 week</option>
+                <option value="2">For 2 weeks</option>
+                <option value="4">For 4 weeks</option>
+                <option value="12">For 12 weeks</option>
+            </select>
+        </label>
+        <button type="submit" name="action" value="save">Save preferences</button>
+        <button type="submit" name="action" value="unsubscribe">Unsubscribe from everything</button>
+    </form>
+    <form action="/subscriptions/manage/email?token={}" method="post">
+        <label>New email address
+            <input type="email" name="email">
+        </label>
+        <button type="submit">Change address</button>
+    </form>
+    <p><a href="/subscriptions/manage/export?token={}">Download your data</a></p>
+    <form action="/subscriptions/manage/erase?token={}" method="post">
+        <button type="submit">Erase all your data</button>
+    </form>
+</body>
+</html>"#,
+        htmlescape::encode_minimal(&subscriber.email),
+        errors,
+        pause,
+        pending_email,
+        htmlescape::encode_attribute(token),
+        htmlescape::encode_attribute(&subscriber.name),
+        lists,
+        htmlescape::encode_attribute(token),
+        htmlescape::encode_attribute(token),
+        htmlescape::encode_attribute(token)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> Vec<ListChoice> {
+        vec![ListChoice {
+            slug: "weekly".into(),
+            name: "Weekly".into(),
+            status: None,
+        }]
+    }
+
+    fn form(pause_weeks: &str, lists: &[&str]) -> PreferencesForm {
+        PreferencesForm {
+            name: "Test User".into(),
+            lists: lists.iter().map(|slug| slug.to_string()).collect(),
+            pause_weeks: Some(pause_weeks.into()),
+            action: None,
+        }
+    }
+
+    #[test]
+    fn repeated_list_fields_are_collected() {
+        let form = PreferencesForm::from(vec![
+            ("name".into(), "Test User".into()),
+            ("lists".into(), "default".into()),
+            ("lists".into(), "weekly".into()),
+        ]);
+        assert_eq!(form.lists, ["default", "weekly"]);
+    }
+
+    #[test]
+    fn pause_weeks_are_bounded() {
+        let now = Utc::now();
+        let update = form("4", &["weekly"]).validate(&lists(), now).unwrap();
+        assert_eq!(update.paused_until, Some(Some(now + Duration::weeks(4))));
+        let update = form("0", &[]).validate(&lists(), now).unwrap();
+        assert_eq!(update.paused_until, Some(None));
+        let update = form("", &[]).validate(&lists(), now).unwrap();
+        assert_eq!(update.paused_until, None);
+        assert!(form("53", &[]).validate(&lists(), now).is_err());
+        assert!(form("-1", &[]).validate(&lists(), now).is_err());
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let mut form = form("99", &["missing"]);
+        form.name = "<script>".into();
+        let errors = form.validate(&lists(), Utc::now()).err().unwrap();
+        assert_eq!(errors.len(), 3);
+    }
+}
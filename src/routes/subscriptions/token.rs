@@ -1,58 +1,301 @@
+use crate::configuration::TokenSettings;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
 
-/// Insert a randomly generated token for the given subscriber ID, then return the token to the
-/// caller.
+type HmacSha256 = Hmac<Sha256>;
+
+/// A subscription token as stored in the token table.
+///
+/// Only a keyed hash of the token is stored, so the plaintext token is never available from the
+/// database.
+pub struct TokenRecord {
+    pub subscriber_id: uuid::Uuid,
+    pub purpose: TokenPurpose,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What following a token confirms.
+pub enum TokenPurpose {
+    /// The subscriber's subscription to a list. These tokens also unsubscribe from the list.
+    Subscription { list_id: uuid::Uuid },
+    /// A change of the subscriber's address to a new one, which received the token.
+    EmailChange { new_email: String },
+}
+
+impl TokenRecord {
+    /// Whether the token is past its expiry time and may no longer be used for confirmation.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+/// Insert a randomly generated token for the given subscriber ID and list, then return the token
+/// to the caller. The token expires after the configured TTL, and only its hash is stored.
 pub async fn insert_token_for_id(
     id: uuid::Uuid,
+    list_id: uuid::Uuid,
+    settings: &TokenSettings,
     pool: &mut sqlx::Transaction<'_, sqlx::Postgres>,
 ) -> Result<String, sqlx::Error> {
     let token = generate_token();
+    let created_at = Utc::now();
     sqlx::query!(
         r#"
-        INSERT INTO tokens (subscriber_id, subscription_token)
-        VALUES ($1, $2)
+        INSERT INTO tokens (subscriber_id, list_id, token_hash, hashed, created_at, expires_at)
+        VALUES ($1, $2, $3, true, $4, $5)
         "#,
         id,
-        token
+        list_id,
+        hash_token(&token, settings),
+        created_at,
+        created_at + settings.ttl()
     )
     .execute(pool)
     .await?;
     Ok(token)
 }
 
-/// Query the token table for a token matching the provided ID. Return token to caller.
-pub async fn get_token_for_id(
+/// Replace every token held by the given subscriber ID for the list with a freshly generated one,
+/// then return the new token to the caller. Tokens for other lists are left alone.
+pub async fn rotate_token_for_id(
     id: uuid::Uuid,
-    pool: &sqlx::PgPool,
-) -> Result<Option<String>, sqlx::Error> {
-    let query_result = sqlx::query!(
+    list_id: uuid::Uuid,
+    settings: &TokenSettings,
+    pool: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<String, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM tokens
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        id,
+        list_id
+    )
+    .execute(&mut *pool)
+    .await?;
+    insert_token_for_id(id, list_id, settings, pool).await
+}
+
+/// Issue a token confirming a change of the subscriber's address, replacing any change they
+/// requested before, then return the token to the caller. The token expires after the configured
+/// TTL, like subscription tokens.
+pub async fn insert_email_change_token(
+    id: uuid::Uuid,
+    new_email: &str,
+    settings: &TokenSettings,
+    pool: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<String, sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT subscription_token FROM tokens
-        WHERE subscriber_id = $1
+        DELETE FROM tokens
+        WHERE subscriber_id = $1 AND new_email IS NOT NULL
         "#,
         id
     )
-    .fetch_optional(pool)
+    .execute(&mut *pool)
+    .await?;
+    let token = generate_token();
+    let created_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO tokens (subscriber_id, new_email, token_hash, hashed, created_at, expires_at)
+        VALUES ($1, $2, $3, true, $4, $5)
+        "#,
+        id,
+        new_email,
+        hash_token(&token, settings),
+        created_at,
+        created_at + settings.ttl()
+    )
+    .execute(pool)
     .await?;
-    Ok(query_result.map(|a| a.subscription_token))
+    Ok(token)
 }
 
-/// Query the token table for an ID matching the provided token. Return ID to caller.
+/// Query the token table for an ID matching the provided token. Return the token record to the
+/// caller so that it can decide whether an expired token is acceptable.
+///
+/// The row is found through the token's keyed hash, so lookup timing reveals nothing useful about
+/// the plaintext token. The stored hash is then checked against the token in constant time.
 pub async fn get_id_for_token(
     token: String,
+    settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<Option<TokenRecord>, sqlx::Error> {
+    let query_result = sqlx::query!(
+        r#"
+        SELECT subscriber_id, list_id, new_email, expires_at, token_hash FROM tokens
+        WHERE token_hash = $1 AND hashed
+        "#,
+        hash_token(&token, settings)
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(query_result
+        .filter(|row| verify_token(&token, &row.token_hash, settings))
+        .and_then(|row| {
+            // The table's check constraint guarantees that exactly one of these is set.
+            let purpose = match (row.list_id, row.new_email) {
+                (Some(list_id), _) => TokenPurpose::Subscription { list_id },
+                (None, Some(new_email)) => TokenPurpose::EmailChange { new_email },
+                (None, None) => return None,
+            };
+            Some(TokenRecord {
+                subscriber_id: row.subscriber_id,
+                purpose,
+                expires_at: row.expires_at,
+            })
+        }))
+}
+
+/// Mark the given token used, returning whether this was its first use. Tokens are kept until
+/// they expire, so that following a link again can be told apart from an invalid token.
+pub async fn use_token(
+    token: &str,
+    settings: &TokenSettings,
+    pool: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE tokens SET used_at = $2
+        WHERE token_hash = $1 AND hashed AND used_at IS NULL
+        "#,
+        hash_token(token, settings),
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Return the management token for the subscriber's preference page, creating it the first time.
+///
+/// The token is signed over the subscriber's ID and a link ID stored in `management_tokens`, so
+/// the same token comes back every time it is asked for: links already sent out keep working, and
+/// every email can carry one. Replacing the link ID revokes the token.
+pub async fn management_token<'e, E>(
+    subscriber_id: uuid::Uuid,
+    settings: &TokenSettings,
+    executor: E,
+) -> Result<String, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    // Subscribers holding a link from before links were signed get a link ID the first time.
+    let link_id = sqlx::query!(
+        r#"
+        INSERT INTO management_tokens (subscriber_id, link_id, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (subscriber_id) DO UPDATE
+        SET link_id = COALESCE(management_tokens.link_id, EXCLUDED.link_id)
+        RETURNING link_id AS "link_id!"
+        "#,
+        subscriber_id,
+        uuid::Uuid::new_v4(),
+        Utc::now()
+    )
+    .fetch_one(executor)
+    .await?
+    .link_id;
+    Ok(sign_token(MANAGE_PURPOSE, subscriber_id, link_id, settings))
+}
+
+/// Find the subscriber holding the given management token. Management tokens don't expire.
+pub async fn get_subscriber_for_management_token(
+    token: &str,
+    settings: &TokenSettings,
     pool: &sqlx::PgPool,
 ) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+    if let Some((subscriber_id, link_id)) = verify_signed_token(MANAGE_PURPOSE, token, settings) {
+        let query_result = sqlx::query!(
+            r#"
+            SELECT subscriber_id FROM management_tokens
+            WHERE subscriber_id = $1 AND link_id = $2
+            "#,
+            subscriber_id,
+            link_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        return Ok(query_result.map(|row| row.subscriber_id));
+    }
+
+    // Links handed out before management tokens were signed hold a random token, which is found
+    // through its keyed hash like subscription tokens.
     let query_result = sqlx::query!(
         r#"
-        SELECT subscriber_id FROM tokens
-        WHERE subscription_token = $1
+        SELECT subscriber_id, token_hash AS "token_hash!" FROM management_tokens
+        WHERE token_hash = $1
         "#,
-        token
+        hash_token(token, settings)
     )
     .fetch_optional(pool)
     .await?;
-    Ok(query_result.map(|a| a.subscriber_id))
+    Ok(query_result
+        .filter(|row| verify_token(token, &row.token_hash, settings))
+        .map(|row| row.subscriber_id))
+}
+
+/// Sign a token that unsubscribes the subscriber from the list, for the links in newsletter
+/// issues.
+///
+/// Nothing is stored: the token carries both IDs along with a MAC over them, so a link can be
+/// minted for every recipient of an issue without touching the token table. It stays valid for
+/// as long as the HMAC secret does.
+pub fn sign_unsubscribe_token(
+    subscriber_id: uuid::Uuid,
+    list_id: uuid::Uuid,
+    settings: &TokenSettings,
+) -> String {
+    sign_token(UNSUBSCRIBE_PURPOSE, subscriber_id, list_id, settings)
+}
+
+/// Check a token made by `sign_unsubscribe_token`, returning the subscriber and list IDs it was
+/// signed for.
+pub fn verify_unsubscribe_token(
+    token: &str,
+    settings: &TokenSettings,
+) -> Option<(uuid::Uuid, uuid::Uuid)> {
+    verify_signed_token(UNSUBSCRIBE_PURPOSE, token, settings)
+}
+
+/// Hash any tokens that were stored in plaintext before tokens were hashed at rest, returning the
+/// number of tokens converted.
+///
+/// Each row is only converted once, so this is safe to run from several instances at the same
+/// time.
+pub async fn hash_legacy_tokens(
+    settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<u64, sqlx::Error> {
+    let legacy_tokens = sqlx::query!(
+        r#"
+        SELECT token_hash AS plaintext FROM tokens
+        WHERE NOT hashed
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut converted = 0;
+    for row in legacy_tokens {
+        converted += sqlx::query!(
+            r#"
+            UPDATE tokens SET token_hash = $1, hashed = true
+            WHERE token_hash = $2 AND NOT hashed
+            "#,
+            hash_token(&row.plaintext, settings),
+            row.plaintext
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+    }
+    Ok(converted)
 }
 
 /// Randomly generate a subscription token.
@@ -63,3 +306,151 @@ fn generate_token() -> String {
         .take(25)
         .collect()
 }
+
+fn token_mac(token: &str, settings: &TokenSettings) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(settings.hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(token.as_bytes());
+    mac
+}
+
+/// What a signed token is for, mixed into its MAC so that a signature made for one purpose is
+/// never accepted for another.
+const UNSUBSCRIBE_PURPOSE: &str = "unsubscribe";
+const MANAGE_PURPOSE: &str = "manage";
+
+/// Sign a pair of IDs for the given purpose, as `{first}.{second}.{mac}`.
+fn sign_token(
+    purpose: &str,
+    first: uuid::Uuid,
+    second: uuid::Uuid,
+    settings: &TokenSettings,
+) -> String {
+    let payload = format!("{}.{}", first, second);
+    let signature = signed_token_mac(purpose, &payload, settings)
+        .finalize()
+        .into_bytes();
+    format!("{}.{}", payload, hex::encode(signature))
+}
+
+/// Check a token made by `sign_token` for the same purpose, returning the IDs it was signed for.
+fn verify_signed_token(
+    purpose: &str,
+    token: &str,
+    settings: &TokenSettings,
+) -> Option<(uuid::Uuid, uuid::Uuid)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let (first, second) = payload.split_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    signed_token_mac(purpose, payload, settings)
+        .verify_slice(&signature)
+        .ok()?;
+    Some((first.parse().ok()?, second.parse().ok()?))
+}
+
+/// The MAC of a signed token's payload. Stored tokens are alphanumeric, so the `:` keeps these
+/// MACs apart from the hashes of stored tokens.
+fn signed_token_mac(purpose: &str, payload: &str, settings: &TokenSettings) -> HmacSha256 {
+    token_mac(&format!("{}:{}", purpose, payload), settings)
+}
+
+/// Compute the hex encoded keyed hash under which a token is stored.
+fn hash_token(token: &str, settings: &TokenSettings) -> String {
+    hex::encode(token_mac(token, settings).finalize().into_bytes())
+}
+
+/// Check a token against a stored hash in constant time.
+fn verify_token(token: &str, stored_hash: &str, settings: &TokenSettings) -> bool {
+    match hex::decode(stored_hash) {
+        Ok(bytes) => token_mac(token, settings).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn settings(secret: &str) -> TokenSettings {
+        TokenSettings {
+            ttl_secs: 60,
+            hmac_secret: Secret::new(secret.into()),
+        }
+    }
+
+    #[test]
+    fn hash_is_not_plaintext() {
+        let token = generate_token();
+        assert_ne!(hash_token(&token, &settings("key")), token);
+    }
+
+    #[test]
+    fn hash_depends_on_secret() {
+        let token = generate_token();
+        assert_ne!(
+            hash_token(&token, &settings("key")),
+            hash_token(&token, &settings("other key"))
+        );
+    }
+
+    #[test]
+    fn verify_accepts_matching_token() {
+        let settings = settings("key");
+        let token = generate_token();
+        let stored = hash_token(&token, &settings);
+        assert!(verify_token(&token, &stored, &settings));
+    }
+
+    #[test]
+    fn verify_rejects_other_token() {
+        let settings = settings("key");
+        let stored = hash_token(&generate_token(), &settings);
+        assert!(!verify_token(&generate_token(), &stored, &settings));
+    }
+
+    #[test]
+    fn verify_rejects_malformed_hash() {
+        let settings = settings("key");
+        let token = generate_token();
+        assert!(!verify_token(&token, &token, &settings));
+    }
+
+    #[test]
+    fn signed_unsubscribe_token_round_trips() {
+        let settings = settings("key");
+        let (subscriber_id, list_id) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let token = sign_unsubscribe_token(subscriber_id, list_id, &settings);
+        assert_eq!(
+            verify_unsubscribe_token(&token, &settings),
+            Some((subscriber_id, list_id))
+        );
+    }
+
+    #[test]
+    fn signed_unsubscribe_token_rejects_other_secret() {
+        let token =
+            sign_unsubscribe_token(uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), &settings("key"));
+        assert_eq!(
+            verify_unsubscribe_token(&token, &settings("other key")),
+            None
+        );
+    }
+
+    #[test]
+    fn signed_tokens_are_bound_to_their_purpose() {
+        let settings = settings("key");
+        let token = sign_unsubscribe_token(uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), &settings);
+        assert_eq!(verify_signed_token(MANAGE_PURPOSE, &token, &settings), None);
+    }
+
+    #[test]
+    fn signed_unsubscribe_token_rejects_swapped_list() {
+        let settings = settings("key");
+        let subscriber_id = uuid::Uuid::new_v4();
+        let token = sign_unsubscribe_token(subscriber_id, uuid::Uuid::new_v4(), &settings);
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}.{}", subscriber_id, uuid::Uuid::new_v4(), signature);
+        assert_eq!(verify_unsubscribe_token(&forged, &settings), None);
+    }
+}
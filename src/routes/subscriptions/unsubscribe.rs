@@ -0,0 +1,129 @@
+use super::token::{self, TokenPurpose};
+use super::Token;
+use crate::configuration::TokenSettings;
+use crate::error::AppError;
+use crate::startup::AppBaseUrl;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+
+/// Remove a subscriber from a mailing list.
+///
+/// This endpoint is the target of the unsubscribe link handed out to subscribers. It uses the
+/// signed token from a newsletter issue, or the subscriber's stored token, to find the
+/// subscription, then marks it as unsubscribed. Subscriptions to other lists are kept.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The subscriber has left the list."),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Subscriber unsubscribe endpoint",
+    skip(query, pool, token_settings)
+)]
+pub async fn handle_unsubscribe(
+    query: web::Query<Token>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    unsubscribe_token(&query.token, &token_settings, &pool).await
+}
+
+/// One-click variant of the unsubscribe endpoint.
+///
+/// Mail clients implementing RFC 8058 send a `POST` to the `List-Unsubscribe` URL with the body
+/// `List-Unsubscribe=One-Click`. The token is carried in the query string, so the body itself is
+/// not inspected.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(Token),
+    responses(
+        (status = 200, description = "The subscriber has left the list."),
+        (status = 400, description = "The token is missing.", body = ErrorBody),
+        (status = 401, description = "The token is not valid.", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Subscriber one-click unsubscribe endpoint",
+    skip(query, pool, token_settings)
+)]
+pub async fn handle_one_click_unsubscribe(
+    query: web::Query<Token>,
+    pool: web::Data<sqlx::PgPool>,
+    token_settings: web::Data<TokenSettings>,
+) -> Result<HttpResponse, AppError> {
+    unsubscribe_token(&query.token, &token_settings, &pool).await
+}
+
+/// Build the link that unsubscribes the subscriber from the list, for the footer and
+/// `List-Unsubscribe` header of newsletter issues.
+pub fn unsubscribe_link(
+    subscriber_id: uuid::Uuid,
+    list_id: uuid::Uuid,
+    token_settings: &TokenSettings,
+    base_url: &AppBaseUrl,
+) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url.0,
+        token::sign_unsubscribe_token(subscriber_id, list_id, token_settings)
+    )
+}
+
+async fn unsubscribe_token(
+    token: &str,
+    token_settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<HttpResponse, AppError> {
+    if let Some((subscriber_id, list_id)) = token::verify_unsubscribe_token(token, token_settings) {
+        unsubscribe_id(subscriber_id, list_id, pool).await?;
+        tracing::info!("User unsubscribe successful!");
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    // Expired tokens are still accepted here: they only limit how long a confirmation link
+    // stays valid, and a subscriber must always be able to leave the list.
+    let record = token::get_id_for_token(token.to_owned(), token_settings, pool)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+    let list_id = match record.purpose {
+        TokenPurpose::Subscription { list_id } => list_id,
+        TokenPurpose::EmailChange { .. } => return Err(AppError::InvalidToken),
+    };
+
+    unsubscribe_id(record.subscriber_id, list_id, pool).await?;
+    tracing::info!("User unsubscribe successful!");
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Mark the subscriber as unsubscribed from the list. Unsubscribing twice keeps the original
+/// timestamp.
+async fn unsubscribe_id(
+    id: uuid::Uuid,
+    list_id: uuid::Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'unsubscribed',
+            unsubscribed_at = COALESCE(unsubscribed_at, $3)
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        id,
+        list_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
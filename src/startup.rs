@@ -1,11 +1,27 @@
-use crate::configuration::Settings;
+use crate::authentication::{AppSessionStore, InMemorySessionStore, PgSessionStore, RequireLogin};
+use crate::configuration::{
+    ApiDocsSettings, BotProtectionSettings, HealthSettings, MetricsSettings, RateLimitSettings,
+    RateLimitStoreKind, SessionSettings, SessionStoreKind, Settings, TokenSettings,
+};
+use crate::confirmation_worker::run_confirmation_worker_until_stopped;
+use crate::domain::EmailDomainPolicy;
+use crate::error::{validation_error, ErrorRequestId};
+use crate::issue_delivery_worker::run_worker_until_stopped;
 use crate::mail::EmailClient;
+use crate::metrics::{handle_metrics, RequestMetrics, SubscriberCounts};
+use crate::rate_limit::{InMemoryRateLimitStore, PgRateLimitStore, RateLimit, RateLimitStore};
 use crate::routes::*;
+use actix_session::config::PersistentSession;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::{time, Key};
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
+use secrecy::ExposeSecret;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
+use utoipa_swagger_ui::{Config as SwaggerConfig, SwaggerUi};
 
 /// Structure used to contain information about a running z2p app server.
 pub struct AppInfo {
@@ -15,6 +31,10 @@ pub struct AppInfo {
     pub app_address: String,
     /// Connection port
     pub app_port: String,
+    /// Background task delivering queued newsletter issues.
+    pub worker: JoinHandle<()>,
+    /// Background task sending confirmation emails queued by imports.
+    pub confirmation_worker: JoinHandle<()>,
 }
 
 impl AppInfo {
@@ -26,17 +46,22 @@ impl AppInfo {
         let app_port = listener.local_addr().unwrap().port().to_string();
 
         // Email Client Setup
-        let sender = configuration
-            .email_client
-            .sender()
-            .expect("Failed to parse sender email");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            sender,
-            configuration.email_client.base_url,
-            configuration.email_client.auth_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.client();
+        let domain_policy = configuration.email_domains.policy()?;
+
+        // Delivery worker, running alongside the server.
+        let worker = tokio::spawn(run_worker_until_stopped(
+            db_connection.clone(),
+            configuration.email_client.client(),
+            AppBaseUrl(configuration.app.base_url.clone()),
+            configuration.tokens.clone(),
+        ));
+        let confirmation_worker = tokio::spawn(run_confirmation_worker_until_stopped(
+            db_connection.clone(),
+            configuration.email_client.client(),
+            AppBaseUrl(configuration.app.base_url.clone()),
+            configuration.tokens.clone(),
+        ));
 
         // FIRE!
         match run(
@@ -44,11 +69,21 @@ impl AppInfo {
             db_connection,
             email_client,
             configuration.app.base_url,
+            configuration.tokens,
+            configuration.session,
+            configuration.health,
+            configuration.api_docs,
+            configuration.metrics,
+            configuration.rate_limit,
+            configuration.bot_protection,
+            domain_policy,
         ) {
             Ok(srv) => Ok(AppInfo {
                 server: srv,
                 app_address,
                 app_port,
+                worker,
+                confirmation_worker,
             }),
             Err(e) => Err(e),
         }
@@ -58,26 +93,157 @@ impl AppInfo {
 #[derive(Debug)]
 pub struct AppBaseUrl(pub String);
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_connection: PgPool,
     email_client: EmailClient,
     base_url: String,
+    token_settings: TokenSettings,
+    session_settings: SessionSettings,
+    health_settings: HealthSettings,
+    api_docs_settings: ApiDocsSettings,
+    metrics_settings: MetricsSettings,
+    rate_limit_settings: RateLimitSettings,
+    bot_protection_settings: BotProtectionSettings,
+    domain_policy: EmailDomainPolicy,
 ) -> std::io::Result<Server> {
     let db_connection = web::Data::new(db_connection);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(AppBaseUrl(base_url));
+    let token_settings = web::Data::new(token_settings);
+    let health_settings = web::Data::new(health_settings);
+    let bot_protection_settings = web::Data::new(bot_protection_settings);
+    let domain_policy = web::Data::new(domain_policy);
+    let metrics_settings = web::Data::new(metrics_settings);
+    let subscriber_counts = web::Data::new(SubscriberCounts::default());
+    let session_store = match session_settings.store {
+        SessionStoreKind::Postgres => {
+            AppSessionStore::Postgres(PgSessionStore::new(db_connection.get_ref().clone()))
+        }
+        SessionStoreKind::Memory => AppSessionStore::Memory(InMemorySessionStore::default()),
+    };
+    let rate_limit_store = match rate_limit_settings.store {
+        RateLimitStoreKind::Postgres => {
+            RateLimitStore::Postgres(PgRateLimitStore::new(db_connection.get_ref().clone()))
+        }
+        RateLimitStoreKind::Memory => RateLimitStore::Memory(InMemoryRateLimitStore::default()),
+    };
+    let rate_limit = RateLimit::new(rate_limit_store, rate_limit_settings);
+    let session_key = Key::try_from(session_settings.secret_key.expose_secret().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let session_ttl = time::Duration::seconds(session_settings.ttl_secs);
+    let cookie_secure = session_settings.cookie_secure;
     let srv = HttpServer::new(move || {
         App::new()
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), session_key.clone())
+                    .cookie_secure(cookie_secure)
+                    .session_lifecycle(PersistentSession::default().session_ttl(session_ttl))
+                    .build(),
+            )
+            .wrap(RequestMetrics)
+            .wrap(ErrorRequestId)
             .wrap(TracingLogger::default())
             .route("/", web::get().to(greet))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(handle_subscribe))
+            .route("/health/live", web::get().to(handle_liveness))
+            .route("/health/ready", web::get().to(handle_readiness))
+            .route("/metrics", web::get().to(handle_metrics))
+            .route("/api/openapi.json", web::get().to(handle_openapi))
+            .configure(|cfg| {
+                if api_docs_settings.serve_ui {
+                    cfg.service(
+                        SwaggerUi::new("/api/docs/{_:.*}")
+                            .config(SwaggerConfig::from("/api/openapi.json")),
+                    );
+                }
+            })
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(rate_limit.clone())
+                    .route(web::post().to(handle_subscribe)),
+            )
+            .service(
+                web::resource("/api/v1/subscriptions")
+                    .wrap(rate_limit.clone())
+                    .route(web::post().to(handle_api_subscribe)),
+            )
+            .route(
+                "/subscriptions/challenge",
+                web::get().to(handle_form_challenge),
+            )
             .route("/subscriptions/confirm", web::get().to(handle_confirm))
+            .route("/subscriptions/manage", web::get().to(handle_manage_form))
+            .route("/subscriptions/manage", web::post().to(handle_manage))
+            .route(
+                "/subscriptions/manage/export",
+                web::get().to(handle_export_data),
+            )
+            .route(
+                "/subscriptions/manage/erase",
+                web::post().to(handle_erase_data),
+            )
+            .service(
+                web::resource("/subscriptions/manage/email")
+                    .wrap(rate_limit.clone())
+                    .route(web::post().to(handle_change_email)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(handle_unsubscribe),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(handle_one_click_unsubscribe),
+            )
+            .route("/login", web::get().to(handle_login_form))
+            .route("/login", web::post().to(handle_login))
+            .service(
+                web::scope("/admin")
+                    .wrap(RequireLogin)
+                    .route("/dashboard", web::get().to(handle_admin_dashboard))
+                    .route("/logout", web::post().to(handle_logout))
+                    .route("/newsletters", web::post().to(handle_publish_newsletter))
+                    .route("/imports", web::post().to(handle_import_subscribers))
+                    .route(
+                        "/imports/{import_id}/errors",
+                        web::get().to(handle_import_error_report),
+                    )
+                    .route("/lists", web::get().to(handle_list_lists))
+                    .route("/lists", web::post().to(handle_create_list))
+                    .route(
+                        "/subscribers/export",
+                        web::get().to(handle_admin_export_subscribers),
+                    )
+                    .route(
+                        "/subscribers/data_requests/export",
+                        web::post().to(handle_admin_export_subscriber),
+                    )
+                    .route(
+                        "/subscribers/data_requests/erase",
+                        web::post().to(handle_admin_erase_subscriber),
+                    )
+                    .route("/dead_letters", web::get().to(handle_list_dead_letters))
+                    .route(
+                        "/dead_letters/{dead_letter_id}/replay",
+                        web::post().to(handle_replay_dead_letter),
+                    ),
+            )
             .route("/{name}", web::get().to(greet))
             .app_data(db_connection.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(token_settings.clone())
+            .app_data(health_settings.clone())
+            .app_data(bot_protection_settings.clone())
+            .app_data(domain_policy.clone())
+            .app_data(metrics_settings.clone())
+            .app_data(subscriber_counts.clone())
+            .app_data(web::FormConfig::default().error_handler(validation_error))
+            .app_data(web::JsonConfig::default().error_handler(validation_error))
+            .app_data(web::QueryConfig::default().error_handler(validation_error))
+            .app_data(web::PathConfig::default().error_handler(validation_error))
     })
     .listen(listener)?
     .run();
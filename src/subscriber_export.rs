@@ -0,0 +1,183 @@
+//! Export of the subscriber list, one row per list subscription, as CSV or JSON Lines.
+//!
+//! Rows are written as they come off the database connection, into a pipe that the response body
+//! reads from, so an export of any size only ever holds a pipe's worth of output in memory. A
+//! client reading slowly holds the query back rather than letting output pile up.
+use chrono::{DateTime, Utc};
+use csv_async::AsyncWriter;
+use futures_util::TryStreamExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio_util::io::ReaderStream;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// How many bytes of output may wait for the client before the export pauses.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// Comma-separated values, with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// The status of a list subscription.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    Pending,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+/// Which list subscriptions to export. Every filter left out matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct ExportFilter {
+    pub status: Option<SubscriptionStatus>,
+    pub list_id: Option<Uuid>,
+    /// Only subscriptions made at or after this time.
+    pub subscribed_from: Option<DateTime<Utc>>,
+    /// Only subscriptions made before this time.
+    pub subscribed_to: Option<DateTime<Utc>>,
+}
+
+/// A single list subscription, as exported.
+#[derive(serde::Serialize, Debug)]
+pub struct ExportRow {
+    pub email: String,
+    pub name: String,
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+/// Start exporting the subscriptions matching the filter, returning the stream of output chunks.
+///
+/// The rows are written by a background task, which stops early if the stream is dropped. Once
+/// the response has started there is no way to report an error to the client, so an export that
+/// fails part way through is logged and its output cut short.
+pub fn export_subscribers(
+    filter: ExportFilter,
+    format: ExportFormat,
+    pool: sqlx::PgPool,
+) -> ReaderStream<tokio::io::DuplexStream> {
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    tokio::spawn(
+        async move {
+            match write_subscribers(&filter, format, &pool, writer).await {
+                Ok(rows) => tracing::info!("Exported {} list subscriptions", rows),
+                Err(e) => tracing::error!("Subscriber export cut short: {:?}", e),
+            }
+        }
+        .instrument(tracing::info_span!("Exporting subscribers")),
+    );
+    ReaderStream::new(reader)
+}
+
+/// Write every matching subscription to `output`, returning how many were written.
+async fn write_subscribers<W>(
+    filter: &ExportFilter,
+    format: ExportFormat,
+    pool: &sqlx::PgPool,
+    output: W,
+) -> anyhow::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut rows = sqlx::query_as!(
+        ExportRow,
+        r#"
+        SELECT s.email, s.name, l.slug AS list, ls.status, ls.subscribed_at, ls.unsubscribed_at
+        FROM list_subscriptions ls
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ($1::TEXT IS NULL OR ls.status = $1)
+            AND ($2::uuid IS NULL OR ls.list_id = $2)
+            AND ($3::timestamptz IS NULL OR ls.subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR ls.subscribed_at < $4)
+        ORDER BY ls.subscribed_at, s.email_canonical, l.slug
+        "#,
+        filter.status.map(|status| status.as_str()),
+        filter.list_id,
+        filter.subscribed_from,
+        filter.subscribed_to
+    )
+    .fetch(pool);
+
+    let mut count = 0;
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = AsyncWriter::from_writer(output);
+            writer
+                .write_record(&[
+                    "email",
+                    "name",
+                    "list",
+                    "status",
+                    "subscribed_at",
+                    "unsubscribed_at",
+                ])
+                .await?;
+            while let Some(row) = rows.try_next().await? {
+                let subscribed_at = row.subscribed_at.to_rfc3339();
+                let unsubscribed_at = row
+                    .unsubscribed_at
+                    .map(|at| at.to_rfc3339())
+                    .unwrap_or_default();
+                writer
+                    .write_record(&[
+                        &row.email,
+                        &row.name,
+                        &row.list,
+                        &row.status,
+                        &subscribed_at,
+                        &unsubscribed_at,
+                    ])
+                    .await?;
+                count += 1;
+            }
+            writer.flush().await?;
+        }
+        ExportFormat::Jsonl => {
+            let mut writer = BufWriter::new(output);
+            while let Some(row) = rows.try_next().await? {
+                let mut line = serde_json::to_vec(&row)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+                count += 1;
+            }
+            writer.flush().await?;
+        }
+    }
+    Ok(count)
+}
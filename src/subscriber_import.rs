@@ -0,0 +1,531 @@
+//! Bulk import of subscribers from a CSV file, for moving over from another provider.
+//!
+//! The file needs a header row with `email` and `name` columns; other columns are ignored. Rows
+//! are validated as they are read, and written in batches of [`IMPORT_BATCH_SIZE`], each in its
+//! own transaction, so an import never holds more than one batch in memory. Rows that can't be
+//! imported are recorded with their line number, and make up the import's error report.
+//!
+//! Subscribers are either imported as confirmed, when their consent was collected elsewhere and
+//! an attestation says where, or as pending, in which case a confirmation email is queued for
+//! every new subscription and sent by the [confirmation worker](crate::confirmation_worker).
+//! Existing subscriptions are never downgraded, and unsubscribed addresses stay unsubscribed.
+//! Existing subscribers keep their details. Erased addresses are turned away, as are addresses
+//! whose domain the [`EmailDomainPolicy`] doesn't accept.
+use crate::configuration::TokenSettings;
+use crate::domain::{EmailDomainPolicy, ListSubscriber};
+use crate::lists::get_list_by_slug;
+use crate::personal_data::hash_email;
+use chrono::{DateTime, Utc};
+use csv_async::{AsyncReaderBuilder, AsyncWriter, ByteRecord, Trim};
+use futures_util::StreamExt;
+use std::collections::HashSet;
+use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+/// How many rows are written per transaction.
+pub const IMPORT_BATCH_SIZE: usize = 500;
+
+/// The status imported subscriptions start with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// Consent was collected elsewhere; nothing is sent.
+    Confirmed,
+    /// Each new subscriber is sent a confirmation email.
+    Pending,
+}
+
+impl ImportStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::Pending => "pending",
+        }
+    }
+}
+
+impl std::str::FromStr for ImportStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirmed" => Ok(Self::Confirmed),
+            "pending" => Ok(Self::Pending),
+            other => Err(format!(
+                "{} is not an import status. Use confirmed or pending.",
+                other
+            )),
+        }
+    }
+}
+
+/// How to import a file.
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// Slug of the list to subscribe everyone to.
+    pub list_slug: String,
+    pub status: ImportStatus,
+    /// Where consent was collected. Required for confirmed imports, and only allowed for them.
+    pub attestation: Option<String>,
+    /// The admin running the import, or `None` from the command line.
+    pub admin_user_id: Option<Uuid>,
+}
+
+/// The outcome of a finished import.
+#[derive(serde::Serialize, Debug)]
+pub struct ImportSummary {
+    pub import_id: Uuid,
+    pub imported_rows: i32,
+    pub failed_rows: i32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// A row that couldn't be imported, as listed in the error report.
+#[derive(Debug)]
+pub struct ImportRowError {
+    /// Line of the row in the file, counting the header as line 1.
+    pub line: i64,
+    /// The address as given, or blank if it can't be kept.
+    pub email: String,
+    pub error: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    /// The options or the header row don't allow the import to start.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("The requested mailing list does not exist.")]
+    UnknownList,
+    /// The file stopped being readable part way through. Batches written until then are kept.
+    #[error("The CSV could not be read.")]
+    Csv(#[from] csv_async::Error),
+    #[error("The database could not be reached.")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Import every row of the CSV read from `input`.
+///
+/// The import is recorded before the first row is read, and finished once the last batch is
+/// written. An import that fails part way through keeps the batches written so far, and is left
+/// without a finish time.
+#[tracing::instrument(
+    name = "Importing subscribers",
+    skip(input, settings, domain_policy, pool)
+)]
+pub async fn import_subscribers<R>(
+    input: R,
+    options: &ImportOptions,
+    settings: &TokenSettings,
+    domain_policy: &EmailDomainPolicy,
+    pool: &sqlx::PgPool,
+) -> Result<ImportSummary, ImportError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let attestation = check_attestation(options)?;
+    let list = get_list_by_slug(&options.list_slug, pool)
+        .await?
+        .ok_or(ImportError::UnknownList)?;
+
+    let mut reader = AsyncReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .create_reader(input);
+    let columns = Columns::find(reader.byte_headers().await?)?;
+
+    let import = ImportRun {
+        import_id: Uuid::new_v4(),
+        list_id: list.list_id,
+        status: options.status,
+    };
+    let started_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, list_id, status, attestation, admin_user_id, started_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        import.import_id,
+        import.list_id,
+        import.status.as_str(),
+        attestation,
+        options.admin_user_id,
+        started_at
+    )
+    .execute(pool)
+    .await?;
+
+    let mut batch = Batch::default();
+    let mut records = reader.byte_records();
+    while let Some(record) = records.next().await {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line()) as i64;
+        let subscriber = columns.parse(&record).and_then(|subscriber| {
+            domain_policy
+                .check(&subscriber.email)
+                .map_err(|rejection| rejection.to_string())?;
+            Ok(subscriber)
+        });
+        match subscriber {
+            Ok(subscriber) => batch.rows.push((line, subscriber)),
+            Err(error) => batch.errors.push(ImportRowError {
+                line,
+                email: columns.email(&record),
+                error,
+            }),
+        }
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            write_batch(&import, std::mem::take(&mut batch), settings, pool).await?;
+        }
+    }
+    write_batch(&import, batch, settings, pool).await?;
+
+    let summary = sqlx::query_as!(
+        ImportSummary,
+        r#"
+        UPDATE subscriber_imports SET finished_at = now()
+        WHERE import_id = $1
+        RETURNING import_id, imported_rows, failed_rows, started_at, finished_at
+        "#,
+        import.import_id
+    )
+    .fetch_one(pool)
+    .await?;
+    tracing::info!(
+        import_id = %summary.import_id,
+        imported_rows = summary.imported_rows,
+        failed_rows = summary.failed_rows,
+        "Import finished"
+    );
+    Ok(summary)
+}
+
+/// The rows of an import that couldn't be imported, in file order, or `None` if there is no such
+/// import.
+pub async fn import_errors(
+    import_id: Uuid,
+    pool: &sqlx::PgPool,
+) -> Result<Option<Vec<ImportRowError>>, sqlx::Error> {
+    let exists = sqlx::query!(
+        r#"
+        SELECT import_id FROM subscriber_imports
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(None);
+    }
+    let errors = sqlx::query_as!(
+        ImportRowError,
+        r#"
+        SELECT line, email, error FROM subscriber_import_errors
+        WHERE import_id = $1
+        ORDER BY line
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(errors))
+}
+
+/// Write an error report as CSV, with a `line,email,error` header, returning the writer.
+pub async fn write_error_report<W>(
+    errors: &[ImportRowError],
+    writer: W,
+) -> Result<W, csv_async::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut writer = AsyncWriter::from_writer(writer);
+    writer.write_record(&["line", "email", "error"]).await?;
+    for error in errors {
+        writer
+            .write_record(&[error.line.to_string().as_str(), &error.email, &error.error])
+            .await?;
+    }
+    Ok(writer.into_inner().await?)
+}
+
+fn check_attestation(options: &ImportOptions) -> Result<Option<&str>, ImportError> {
+    let attestation = options
+        .attestation
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty());
+    match (options.status, attestation) {
+        (ImportStatus::Confirmed, None) => Err(ImportError::Invalid(
+            "Importing subscribers as confirmed needs an attestation of where their consent was collected."
+                .into(),
+        )),
+        (ImportStatus::Pending, Some(_)) => Err(ImportError::Invalid(
+            "An attestation only applies to subscribers imported as confirmed.".into(),
+        )),
+        (_, attestation) => Ok(attestation),
+    }
+}
+
+struct ImportRun {
+    import_id: Uuid,
+    list_id: Uuid,
+    status: ImportStatus,
+}
+
+/// Where the fields are in each row.
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn find(headers: &ByteRecord) -> Result<Self, ImportError> {
+        let position = |column: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(column.as_bytes()))
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Self { email, name }),
+            _ => Err(ImportError::Invalid(
+                "The CSV needs a header row with email and name columns.".into(),
+            )),
+        }
+    }
+
+    fn parse(&self, record: &ByteRecord) -> Result<ListSubscriber, String> {
+        let field = |index: usize, column: &str| match record.get(index) {
+            None => Err(format!("The row has no {} field.", column)),
+            Some(bytes) => String::from_utf8(bytes.to_vec())
+                .map_err(|_| format!("The {} field is not valid UTF-8.", column)),
+        };
+        let email = field(self.email, "email")?;
+        let name = field(self.name, "name")?;
+        ListSubscriber::try_new(name, email)
+    }
+
+    /// The address of a row, as far as it can be read.
+    fn email(&self, record: &ByteRecord) -> String {
+        record
+            .get(self.email)
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct Batch {
+    rows: Vec<(i64, ListSubscriber)>,
+    errors: Vec<ImportRowError>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.rows.len() + self.errors.len()
+    }
+}
+
+/// Write a batch of rows and errors in a single transaction, updating the import's counts.
+async fn write_batch(
+    import: &ImportRun,
+    mut batch: Batch,
+    settings: &TokenSettings,
+    pool: &sqlx::PgPool,
+) -> Result<(), sqlx::Error> {
+    if batch.len() == 0 {
+        return Ok(());
+    }
+    let mut txn = pool.begin().await?;
+
+    let hashes: Vec<String> = batch
+        .rows
+        .iter()
+        .map(|(_, subscriber)| hash_email(subscriber.email.canonical(), settings))
+        .collect();
+    let erased: HashSet<String> = sqlx::query!(
+        r#"
+        SELECT email_hash FROM erased_subscribers
+        WHERE email_hash = ANY($1)
+        "#,
+        &hashes[..]
+    )
+    .fetch_all(&mut txn)
+    .await?
+    .into_iter()
+    .map(|row| row.email_hash)
+    .collect();
+
+    let mut imported_rows = 0;
+    for ((line, subscriber), hash) in batch.rows.iter().zip(&hashes) {
+        if erased.contains(hash) {
+            // The address isn't kept in the report, since it was erased.
+            batch.errors.push(ImportRowError {
+                line: *line,
+                email: String::new(),
+                error: "The address was erased at the subscriber's request.".into(),
+            });
+            continue;
+        }
+        upsert_subscriber(import, subscriber, &mut txn).await?;
+        imported_rows += 1;
+    }
+
+    for error in &batch.errors {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_import_errors (import_id, line, email, error)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            import.import_id,
+            error.line,
+            error.email,
+            error.error
+        )
+        .execute(&mut txn)
+        .await?;
+    }
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET imported_rows = imported_rows + $2, failed_rows = failed_rows + $3
+        WHERE import_id = $1
+        "#,
+        import.import_id,
+        imported_rows,
+        batch.errors.len() as i32
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await
+}
+
+/// Add the subscriber unless they are already known, then subscribe them to the import's list.
+///
+/// Known subscribers keep the name they have, which they may have set themselves.
+async fn upsert_subscriber(
+    import: &ImportRun,
+    subscriber: &ListSubscriber,
+    txn: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(), sqlx::Error> {
+    // The second half doesn't see the row the first half inserts, so exactly one of them
+    // returns the subscriber's ID.
+    let subscriber_id = sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (email_canonical) DO NOTHING
+            RETURNING id
+        )
+        SELECT id AS "id!" FROM inserted
+        UNION ALL
+        SELECT id FROM subscriptions WHERE email_canonical = $3
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.email.canonical(),
+        subscriber.name.as_ref(),
+        Utc::now()
+    )
+    .fetch_one(&mut *txn)
+    .await?
+    .id;
+
+    match import.status {
+        ImportStatus::Confirmed => {
+            sqlx::query!(
+                r#"
+                INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, import_id)
+                VALUES ($1, $2, 'confirmed', $3, $4)
+                ON CONFLICT (list_id, subscriber_id) DO UPDATE
+                SET status = 'confirmed', import_id = EXCLUDED.import_id
+                WHERE list_subscriptions.status = 'pending'
+                "#,
+                import.list_id,
+                subscriber_id,
+                Utc::now(),
+                import.import_id
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+        ImportStatus::Pending => {
+            let created = sqlx::query!(
+                r#"
+                INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, import_id)
+                VALUES ($1, $2, 'pending', $3, $4)
+                ON CONFLICT (list_id, subscriber_id) DO NOTHING
+                "#,
+                import.list_id,
+                subscriber_id,
+                Utc::now(),
+                import.import_id
+            )
+            .execute(&mut *txn)
+            .await?
+            .rows_affected()
+                > 0;
+            if created {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO confirmation_email_queue (subscriber_id, list_id)
+                    VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    subscriber_id,
+                    import.list_id
+                )
+                .execute(&mut *txn)
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(status: ImportStatus, attestation: Option<&str>) -> ImportOptions {
+        ImportOptions {
+            list_slug: "default".into(),
+            status,
+            attestation: attestation.map(String::from),
+            admin_user_id: None,
+        }
+    }
+
+    #[test]
+    fn confirmed_imports_need_an_attestation() {
+        assert!(check_attestation(&options(ImportStatus::Confirmed, None)).is_err());
+        assert!(check_attestation(&options(ImportStatus::Confirmed, Some("  "))).is_err());
+        assert_eq!(
+            check_attestation(&options(ImportStatus::Confirmed, Some(" signup form "))).unwrap(),
+            Some("signup form")
+        );
+        assert!(check_attestation(&options(ImportStatus::Pending, Some("form"))).is_err());
+        assert_eq!(
+            check_attestation(&options(ImportStatus::Pending, None)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn columns_are_found_by_header() {
+        let headers = ByteRecord::from(vec!["Name", "id", "EMAIL"]);
+        let columns = Columns::find(&headers).unwrap();
+        let row = ByteRecord::from(vec!["Ursula Le Guin", "7", "ursula@example.com"]);
+        let subscriber = columns.parse(&row).unwrap();
+        assert_eq!(subscriber.email.as_ref(), "ursula@example.com");
+
+        let short_row = ByteRecord::from(vec!["Ursula Le Guin"]);
+        assert!(columns.parse(&short_row).is_err());
+        assert!(Columns::find(&ByteRecord::from(vec!["email"])).is_err());
+    }
+}
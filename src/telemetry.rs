@@ -1,25 +1,97 @@
+use crate::configuration::TelemetrySettings;
+use opentelemetry::global;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
 use tracing::subscriber::{set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+/// Compose the app's subscriber: Bunyan formatted logs written to `sink`, and, when a tracer is
+/// given, spans exported as OpenTelemetry traces.
 pub fn get_subscriber<Sink>(
     name: String,
     filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(filter));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(otel_layer)
 }
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // Make sure logger logs wind up in the tracer output.
     tracing_log::LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber!");
 }
+
+/// Build a tracer exporting spans to the configured OTLP collector, or `None` if no collector is
+/// configured.
+///
+/// Spans are exported in batches from a background task, so this must be called from within a
+/// Tokio runtime. Call [`shutdown_tracing`] before exiting to flush the last batch.
+pub fn get_otlp_tracer(
+    name: &str,
+    settings: &TelemetrySettings,
+) -> Result<Option<Tracer>, TraceError> {
+    let endpoint = match &settings.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    // W3C trace context is what gets attached to outgoing requests by `inject_trace_context`.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                // The HTTP exporter posts to the endpoint as given, so the path is added here.
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .with_timeout(settings.otlp_timeout()),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                name.to_owned(),
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(Some(tracer))
+}
+
+/// Export any spans still waiting in the batch. A no-op when traces aren't exported.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Add the current span's trace context to the headers of an outgoing request, so that the
+/// receiving service can join the same trace.
+pub fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut opentelemetry_http::HeaderInjector(headers))
+    });
+}
+
+/// Run a blocking closure on the blocking thread pool, inside the caller's tracing span.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
@@ -0,0 +1,244 @@
+use crate::newsletters::newsletter_body;
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    async fn get_dead_letters(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}:{}/admin/dead_letters",
+                self.app_address, self.app_port
+            ))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+    async fn replay_dead_letter(&self, dead_letter_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/dead_letters/{}/replay",
+                self.app_address, self.app_port, dead_letter_id
+            ))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+}
+
+/// Publish an issue to a single confirmed subscriber while the mail service rejects everything,
+/// leaving exactly one dead letter behind.
+async fn create_dead_letter(app: &TestApp) {
+    app.create_confirmed_subscriber().await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_dead_lettered() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Test%20User&email=test@example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 502);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "email_delivery_failed");
+    let dead_letter =
+        sqlx::query!("SELECT kind, recipient, body_text, attempts FROM email_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.kind, "confirmation");
+    assert_eq!(dead_letter.recipient, "test@example.com");
+    assert_eq!(dead_letter.attempts, 3);
+    // The body holds the confirmation token, so it isn't kept.
+    assert_eq!(dead_letter.body_text, None);
+}
+
+#[tokio::test]
+async fn replaying_a_confirmation_sends_a_fresh_token() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_subscriptions("name=Test%20User&email=test@example.com".into())
+            .await;
+    }
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    assert_eq!(body[0]["body_text"], serde_json::Value::Null);
+    let dead_letter_id = body[0]["dead_letter_id"].as_str().unwrap().to_owned();
+
+    // Act
+    let response = {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.replay_dead_letter(&dead_letter_id).await
+    };
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_links(&email_request);
+    app.confirm(&links.html).await;
+    let status = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn dead_letters_are_listed() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    create_dead_letter(&app).await;
+
+    // Act
+    let response = app.get_dead_letters().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let dead_letters = body.as_array().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0]["kind"], "newsletter");
+    assert_eq!(dead_letters[0]["subject"], "Newsletter title");
+    assert_eq!(dead_letters[0]["attempts"], 1);
+    // Issues carry the subscriber's unsubscribe link, so they are rendered again on replay.
+    assert_eq!(dead_letters[0]["body_text"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn replaying_a_dead_letter_delivers_it() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    create_dead_letter(&app).await;
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    let dead_letter_id = body[0]["dead_letter_id"].as_str().unwrap().to_owned();
+
+    // Act
+    let response = {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.replay_dead_letter(&dead_letter_id).await
+    };
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    assert!(body.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn replaying_a_failing_dead_letter_keeps_it() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    create_dead_letter(&app).await;
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    let dead_letter_id = body[0]["dead_letter_id"].as_str().unwrap().to_owned();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.replay_dead_letter(&dead_letter_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 502);
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    assert_eq!(body[0]["attempts"], 2);
+}
+
+#[tokio::test]
+async fn retried_replay_with_the_same_key_sends_once() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    create_dead_letter(&app).await;
+    let body: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+    let dead_letter_id = body[0]["dead_letter_id"].as_str().unwrap().to_owned();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let replay = || {
+        app.api_client
+            .post(format!(
+                "{}:{}/admin/dead_letters/{}/replay",
+                app.app_address, app.app_port, dead_letter_id
+            ))
+            .header("Idempotency-Key", "replay-once")
+            .send()
+    };
+
+    // Act
+    let (first, second) = tokio::join!(replay(), replay());
+
+    // Assert
+    assert_eq!(first.unwrap().status().as_u16(), 200);
+    assert_eq!(second.unwrap().status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn replaying_an_unknown_dead_letter_returns_404() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app
+        .replay_dead_letter(&uuid::Uuid::new_v4().to_string())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
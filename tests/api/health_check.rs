@@ -1,4 +1,6 @@
 use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -19,3 +21,109 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(response.content_length(), Some(0));
 }
+
+impl TestApp {
+    async fn get_health(&self, probe: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}:{}/health/{}",
+                self.app_address, self.app_port, probe
+            ))
+            .send()
+            .await
+            .expect("Request failed to execute.")
+    }
+}
+
+#[tokio::test]
+async fn liveness_probe_reports_up() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.get_health("live").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_probe_reports_database_status_and_latency() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert!(body["checks"]["database"]["latency_ms"].is_u64());
+    assert!(body["checks"].get("email").is_none());
+}
+
+#[tokio::test]
+async fn readiness_probe_returns_503_when_database_is_down() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    // The app shares this pool, so closing it cuts the app off from Postgres.
+    app.db_pool.close().await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["database"]["status"], "down");
+    // The cause is only logged.
+    assert!(body["checks"]["database"].get("error").is_none());
+}
+
+#[tokio::test]
+async fn readiness_probe_checks_email_provider_when_enabled() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.health.check_email_provider = true).await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_probe_reports_a_down_email_provider_but_stays_ready() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.health.check_email_provider = true).await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["email"]["status"], "down");
+    assert!(body["checks"]["email"].get("error").is_none());
+}
@@ -0,0 +1,126 @@
+use crate::newsletters::newsletter_body;
+use crate::setup::TestApp;
+
+impl TestApp {
+    async fn post_newsletters_with_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/newsletters",
+                self.app_address, self.app_port
+            ))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+}
+
+async fn count_issues(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT COUNT(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn count_queued_deliveries(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn retried_publish_is_only_queued_once() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let first = app
+        .post_newsletters_with_key(newsletter_body(), &idempotency_key)
+        .await;
+    let second = app
+        .post_newsletters_with_key(newsletter_body(), &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    assert_eq!(count_issues(&app).await, Some(1));
+    assert_eq!(count_queued_deliveries(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn concurrent_publishes_with_the_same_key_are_only_queued_once() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let (first, second) = tokio::join!(
+        app.post_newsletters_with_key(newsletter_body(), &idempotency_key),
+        app.post_newsletters_with_key(newsletter_body(), &idempotency_key)
+    );
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_eq!(count_issues(&app).await, Some(1));
+    assert_eq!(count_queued_deliveries(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn publishes_with_different_keys_are_both_queued() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+
+    // Act
+    for idempotency_key in ["first-issue", "second-issue"] {
+        app.post_newsletters_with_key(newsletter_body(), idempotency_key)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    assert_eq!(count_issues(&app).await, Some(2));
+    assert_eq!(count_queued_deliveries(&app).await, Some(2));
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let test_cases = vec![("".to_string(), "empty"), ("a".repeat(65), "too long")];
+
+    for (idempotency_key, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters_with_key(newsletter_body(), &idempotency_key)
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a key that was {}.",
+            description
+        );
+    }
+    assert_eq!(count_issues(&app).await, Some(0));
+}
@@ -0,0 +1,360 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    pub async fn post_import(&self, query: &[(&str, &str)], csv: String) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/imports",
+                self.app_address, self.app_port
+            ))
+            .query(query)
+            .header("Content-Type", "text/csv")
+            .body(csv)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    async fn get_error_report(&self, location: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}:{}{}",
+                self.app_address, self.app_port, location
+            ))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    async fn imported_status(&self, email: &str) -> Option<String> {
+        sqlx::query!(
+            "SELECT ls.status FROM list_subscriptions ls
+            JOIN subscriptions s ON s.id = ls.subscriber_id
+            WHERE s.email = $1",
+            email
+        )
+        .fetch_optional(&self.db_pool)
+        .await
+        .expect("Failed to query list subscription")
+        .map(|row| row.status)
+    }
+}
+
+const CONFIRMED: [(&str, &str); 2] = [("status", "confirmed"), ("attestation", "Old signup form")];
+
+#[tokio::test]
+async fn confirmed_imports_subscribe_valid_rows_and_report_the_rest() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "name,email,joined\n\
+        Ursula Le Guin,ursula@example.com,2001\n\
+        Nobody,not-an-address,2002\n\
+        Octavia Butler,octavia@example.com,2003\n"
+        .to_string();
+
+    // Act
+    let response = app.post_import(&CONFIRMED, csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["imported_rows"], 2);
+    assert_eq!(summary["failed_rows"], 1);
+    for email in ["ursula@example.com", "octavia@example.com"] {
+        assert_eq!(
+            app.imported_status(email).await.as_deref(),
+            Some("confirmed")
+        );
+    }
+    let attestation = sqlx::query!(
+        "SELECT i.attestation FROM subscriber_imports i
+        JOIN list_subscriptions ls ON ls.import_id = i.import_id
+        LIMIT 1"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .attestation;
+    assert_eq!(attestation.as_deref(), Some("Old signup form"));
+
+    let report = app.get_error_report(&location).await;
+    assert_eq!(report.status().as_u16(), 200);
+    let mut lines: Vec<String> = report
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(lines.remove(0), "line,email,error");
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("3,not-an-address,"), "{}", lines[0]);
+}
+
+#[tokio::test]
+async fn pending_imports_queue_confirmation_emails() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    // Two confirmations, then the welcome.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n".to_string();
+
+    // Act
+    let response = app.post_import(&[("status", "pending")], csv).await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.dispatch_all_pending_confirmations().await;
+
+    // Assert
+    assert_eq!(
+        app.imported_status("ursula@example.com").await.as_deref(),
+        Some("pending")
+    );
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_links(email_request);
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let confirmed = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM list_subscriptions WHERE status = 'confirmed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(confirmed, 1);
+}
+
+#[tokio::test]
+async fn imports_are_written_in_batches() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..1_200 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+
+    // Act
+    let response = app.post_import(&CONFIRMED, csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["imported_rows"], 1_200);
+    let imported = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(imported, 1_200);
+}
+
+#[tokio::test]
+async fn imports_do_not_resubscribe_or_resurrect_addresses() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(links.html.replace("/confirm?", "/unsubscribe?"))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribed = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    app.create_confirmed_subscriber().await;
+    let erased = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE email <> $1",
+        unsubscribed
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .email;
+    let response = app
+        .api_client
+        .post(format!(
+            "{}:{}/admin/subscribers/data_requests/erase",
+            app.app_address, app.app_port
+        ))
+        .json(&serde_json::json!({ "email": erased }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let csv = format!("email,name\n{},Again\n{},Again\n", unsubscribed, erased);
+
+    // Act
+    let response = app.post_import(&CONFIRMED, csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    assert_eq!(
+        app.imported_status(&unsubscribed).await.as_deref(),
+        Some("unsubscribed")
+    );
+    assert_eq!(app.imported_status(&erased).await, None);
+    let report = app.get_error_report(&location).await.text().await.unwrap();
+    assert!(report.contains("3,,"), "{}", report);
+    assert!(!report.contains(&erased), "{}", report);
+}
+
+#[tokio::test]
+async fn imports_keep_the_details_of_existing_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    let existing = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let csv = format!("email,name\n{},Someone Else\n", existing.email);
+
+    // Act
+    let response = app.post_import(&CONFIRMED, csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["imported_rows"], 1);
+    let name = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .name;
+    assert_eq!(name, existing.name);
+}
+
+#[tokio::test]
+async fn imports_turn_away_domains_the_policy_rejects() {
+    // Arrange
+    let app =
+        TestApp::spawn_with(|c| c.email_domains.blocklist = vec!["blocked.test".into()]).await;
+    app.log_in_as_admin().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\n\
+        ursula@example.com,Ursula\n\
+        spam@blocked.test,Blocked\n\
+        throwaway@mailinator.com,Disposable\n"
+        .to_string();
+
+    // Act
+    let response = app.post_import(&[("status", "pending")], csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["imported_rows"], 1);
+    assert_eq!(summary["failed_rows"], 2);
+    assert_eq!(app.imported_status("spam@blocked.test").await, None);
+    assert_eq!(app.imported_status("throwaway@mailinator.com").await, None);
+    app.dispatch_all_pending_confirmations().await;
+    let report = app.get_error_report(&location).await.text().await.unwrap();
+    assert!(report.contains("3,spam@blocked.test,"), "{}", report);
+    assert!(report.contains("4,throwaway@mailinator.com,"), "{}", report);
+}
+
+#[tokio::test]
+async fn invalid_imports_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let csv = "email,name\nursula@example.com,Ursula\n";
+    let test_cases = [
+        (vec![("status", "confirmed")], csv, 400, "no attestation"),
+        (
+            vec![("status", "pending"), ("attestation", "Old signup form")],
+            csv,
+            400,
+            "attestation on a pending import",
+        ),
+        (vec![("status", "subscribed")], csv, 400, "unknown status"),
+        (
+            vec![("status", "pending")],
+            "address,name\nursula@example.com,Ursula\n",
+            400,
+            "no email column",
+        ),
+        (
+            vec![("status", "pending"), ("list", "missing")],
+            csv,
+            404,
+            "unknown list",
+        ),
+    ];
+
+    for (query, csv, status, description) in test_cases {
+        // Act
+        let response = app.post_import(&query, csv.to_string()).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), status, "{}", description);
+    }
+    let imports = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriber_imports")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(imports, 0);
+}
+
+#[tokio::test]
+async fn imports_require_login() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_import(&CONFIRMED, "email,name\nursula@example.com,Ursula\n".into())
+        .await;
+    let report = app
+        .get_error_report(&format!("/admin/imports/{}/errors", uuid::Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(report.status().as_u16(), 303);
+    assert_eq!(app.imported_status("ursula@example.com").await, None);
+}
+
+#[tokio::test]
+async fn error_reports_of_unknown_imports_are_not_found() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app
+        .get_error_report(&format!("/admin/imports/{}/errors", uuid::Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
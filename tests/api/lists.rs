@@ -0,0 +1,272 @@
+use crate::newsletters::newsletter_body;
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    pub async fn post_lists(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/lists",
+                self.app_address, self.app_port
+            ))
+            .json(&body)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    /// Create a list as the admin, who must be logged in.
+    pub async fn create_list(&self, slug: &str) {
+        let response = self
+            .post_lists(serde_json::json!({"slug": slug, "name": format!("The {} list", slug)}))
+            .await;
+        assert_eq!(response.status().as_u16(), 201, "Creating a list failed");
+    }
+
+    /// Subscribe an address to a list, returning the confirmation link from the email it gets.
+    async fn subscribe_to_list(&self, email: &str, list: &str) -> String {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        let response = self
+            .post_api_subscriptions(&serde_json::json!({
+                "name": "Test User",
+                "email": email,
+                "list": list,
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_links(&email_request).html
+    }
+
+    async fn list_status(&self, email: &str, list: &str) -> String {
+        sqlx::query!(
+            "SELECT ls.status FROM list_subscriptions ls
+            JOIN subscriptions s ON s.id = ls.subscriber_id
+            JOIN lists l ON l.list_id = ls.list_id
+            WHERE s.email = $1 AND l.slug = $2",
+            email,
+            list
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to query list subscription")
+        .status
+    }
+}
+
+#[tokio::test]
+async fn admin_can_create_and_list_lists() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app
+        .post_lists(serde_json::json!({"slug": "weekly", "name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(created["slug"], "weekly");
+    let lists: serde_json::Value = app.get("/admin/lists").await.json().await.unwrap();
+    let slugs: Vec<_> = lists
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|list| list["slug"].as_str().unwrap())
+        .collect();
+    assert_eq!(slugs, ["default", "weekly"]);
+}
+
+#[tokio::test]
+async fn creating_a_list_with_a_taken_slug_is_a_conflict() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app
+        .post_lists(serde_json::json!({"slug": "default", "name": "Another"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "conflict");
+}
+
+#[tokio::test]
+async fn creating_a_list_with_invalid_fields_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app
+        .post_lists(serde_json::json!({"slug": "Not A Slug", "name": " "}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["fields"]["slug"].is_string());
+    assert!(body["fields"]["name"].is_string());
+}
+
+#[tokio::test]
+async fn lists_are_only_managed_by_logged_in_users() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_lists(serde_json::json!({"slug": "weekly", "name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_not_found() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let form = app
+        .post_subscriptions("name=Test%20User&email=test%40example.com&list=missing".into())
+        .await;
+    let api = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": "test@example.com",
+            "list": "missing",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 404);
+    assert_eq!(api.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn one_address_can_join_several_lists() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_list("weekly").await;
+    app.subscribe_to_list("test@example.com", "default").await;
+
+    // Act
+    let link = app.subscribe_to_list("test@example.com", "weekly").await;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 1);
+    assert_eq!(
+        app.list_status("test@example.com", "weekly").await,
+        "confirmed"
+    );
+    assert_eq!(
+        app.list_status("test@example.com", "default").await,
+        "pending"
+    );
+}
+
+#[tokio::test]
+async fn unsubscribing_only_leaves_the_token_list() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_list("weekly").await;
+    for list in ["default", "weekly"] {
+        let link = app.subscribe_to_list("test@example.com", list).await;
+        app.confirm(&link).await;
+    }
+
+    // Act
+    let response = reqwest::get(app.unsubscribe_link("test@example.com", "weekly").await)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.list_status("test@example.com", "weekly").await,
+        "unsubscribed"
+    );
+    assert_eq!(
+        app.list_status("test@example.com", "default").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+async fn newsletters_only_go_to_the_chosen_list() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_list("weekly").await;
+    app.create_confirmed_subscriber().await;
+    let link = app.subscribe_to_list("weekly@example.com", "weekly").await;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let mut body = newsletter_body();
+    body["list"] = "weekly".into();
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "weekly@example.com");
+}
+
+#[tokio::test]
+async fn newsletters_to_an_unknown_list_are_not_found() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let mut body = newsletter_body();
+    body["list"] = "missing".into();
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,161 @@
+use crate::setup::TestApp;
+use zero2prod::configuration::SessionStoreKind;
+
+impl TestApp {
+    async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}:{}/admin/dashboard",
+                self.app_address, self.app_port
+            ))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+    async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/logout",
+                self.app_address, self.app_port
+            ))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+}
+
+#[tokio::test]
+async fn login_form_is_served() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}:{}/login", app.app_address, app.app_port))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"action="/login""#));
+}
+
+#[tokio::test]
+async fn invalid_credentials_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let cases = vec![
+        (app.test_user.username.clone(), "wrong-password".to_string()),
+        ("unknown-user".to_string(), app.test_user.password.clone()),
+    ];
+
+    for (username, password) in cases {
+        // Act
+        let response = app.post_login(&username, &password).await;
+
+        // Assert
+        assert_eq!(401, response.status().as_u16());
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("Invalid username or password."));
+        assert_eq!(303, app.get_admin_dashboard().await.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn successful_login_redirects_to_dashboard() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(response.headers()["Location"], "/admin/dashboard");
+    let dashboard = app.get_admin_dashboard().await;
+    assert_eq!(200, dashboard.status().as_u16());
+    assert!(dashboard
+        .text()
+        .await
+        .unwrap()
+        .contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn dashboard_requires_login() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(response.headers()["Location"], "/login");
+}
+
+#[tokio::test]
+async fn logout_ends_the_session() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    // Act
+    let response = app.post_logout().await;
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(response.headers()["Location"], "/login");
+    assert_eq!(303, app.get_admin_dashboard().await.status().as_u16());
+}
+
+#[tokio::test]
+async fn sessions_are_stored_hashed() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    // Assert
+    let cookie = response
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie set");
+    let sessions = sqlx::query!("SELECT session_key_hash FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert!(!cookie.value().contains(&sessions[0].session_key_hash));
+}
+
+#[tokio::test]
+async fn in_memory_session_store_keeps_sessions() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.session.store = SessionStoreKind::Memory).await;
+
+    // Act
+    app.log_in_as_admin().await;
+
+    // Assert
+    assert_eq!(200, app.get_admin_dashboard().await.status().as_u16());
+    let sessions = sqlx::query!("SELECT COUNT(*) AS count FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.count, Some(0));
+}
@@ -1,3 +1,15 @@
+mod dead_letters;
 mod health_check;
+mod idempotency;
+mod imports;
+mod lists;
+mod login;
+mod metrics;
+mod newsletters;
+mod openapi;
+mod personal_data;
+mod rate_limit;
 mod setup;
+mod subscriber_export;
 mod subscriptions;
+mod telemetry;
@@ -0,0 +1,161 @@
+use crate::setup::TestApp;
+use secrecy::ExposeSecret;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+
+impl TestApp {
+    async fn scrape_metrics(&self, token: Option<&str>) -> reqwest::Response {
+        let mut request =
+            reqwest::Client::new().get(format!("{}:{}/metrics", self.app_address, self.app_port));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Request failed to execute.")
+    }
+
+    async fn get_metrics(&self) -> String {
+        let settings = get_configuration().unwrap().metrics;
+        let token = settings.bearer_token.unwrap();
+        let response = self.scrape_metrics(Some(token.expose_secret())).await;
+        assert_eq!(200, response.status().as_u16());
+        response.text().await.unwrap()
+    }
+}
+
+/// Find the value of the sample with exactly the given name and labels.
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn requests_are_counted_and_timed_by_route() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    reqwest::get(format!("{}:{}/health_check", app.app_address, app.app_port))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}:{}/Ferris", app.app_address, app.app_port))
+        .await
+        .unwrap();
+
+    // Act
+    let metrics = app.get_metrics().await;
+
+    // Assert
+    // Other tests share the process-wide registry, so only lower bounds can be checked.
+    let requests = r#"http_requests_total{method="GET",route="/health_check",status="200"}"#;
+    assert!(sample(&metrics, requests).unwrap() >= 1.0);
+    let greetings = r#"http_requests_total{method="GET",route="/{name}",status="200"}"#;
+    assert!(sample(&metrics, greetings).unwrap() >= 1.0);
+    let latency = r#"http_request_duration_seconds_count{method="GET",route="/health_check"}"#;
+    assert!(sample(&metrics, latency).unwrap() >= 1.0);
+}
+
+#[tokio::test]
+async fn extension_methods_share_a_label() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"BREW").unwrap(),
+            format!("{}:{}/health_check", app.app_address, app.app_port),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Act
+    let metrics = app.get_metrics().await;
+
+    // Assert
+    assert!(metrics.contains(r#"method="other""#), "{}", metrics);
+    assert!(!metrics.contains("BREW"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn metrics_require_the_bearer_token() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    for token in [None, Some("not-the-token")] {
+        // Act
+        let response = app.scrape_metrics(token).await;
+
+        // Assert
+        assert_eq!(401, response.status().as_u16(), "{:?}", token);
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_without_a_token() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.metrics.bearer_token = None).await;
+
+    // Act
+    let response = app.scrape_metrics(None).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn pool_connections_are_reported() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let metrics = app.get_metrics().await;
+
+    // Assert
+    assert!(sample(&metrics, r#"db_pool_connections{state="idle"}"#).is_some());
+    assert!(sample(&metrics, r#"db_pool_connections{state="active"}"#).is_some());
+}
+
+#[tokio::test]
+async fn subscribers_are_counted_by_status() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.create_confirmed_subscriber().await;
+    app.create_unconfirmed_subscriber().await;
+    app.create_unconfirmed_subscriber().await;
+
+    // Act
+    let metrics = app.get_metrics().await;
+
+    // Assert
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"subscribers{list="default",status="confirmed"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"subscribers{list="default",status="pending"}"#),
+        Some(2.0)
+    );
+}
+
+#[tokio::test]
+async fn email_sends_are_counted_by_provider_status() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=Test%20User&email=test@example.com".into())
+        .await;
+
+    // Act
+    let metrics = app.get_metrics().await;
+
+    // Assert
+    let failures = r#"email_sends_total{outcome="retryable",status="503",transport="postmark"}"#;
+    assert!(sample(&metrics, failures).unwrap() >= 3.0);
+}
@@ -0,0 +1,331 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+
+pub fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "body_text": "Newsletter body as plain text",
+        "body_html": "<p>Newsletter body as HTML</p>",
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_unconfirmed_subscriber().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    app.create_confirmed_subscriber().await;
+    app.create_unconfirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn newsletter_issues_are_queued_until_dispatched() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    app.create_confirmed_subscriber().await;
+    app.create_unconfirmed_subscriber().await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 2);
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_one_click_unsubscribe_link() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let header = |name: &str| -> String {
+        email_body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == name)
+            .unwrap_or_else(|| panic!("No {} header", name))["Value"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+    let unsubscribe_link = header("List-Unsubscribe");
+    let unsubscribe_link = unsubscribe_link
+        .strip_prefix('<')
+        .and_then(|l| l.strip_suffix('>'))
+        .unwrap();
+    assert!(email_body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(unsubscribe_link));
+
+    let mut unsubscribe_link = reqwest::Url::parse(unsubscribe_link).unwrap();
+    unsubscribe_link
+        .set_port(Some(app.app_port.parse().unwrap()))
+        .unwrap();
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn concurrent_workers_deliver_each_task_once() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    for _ in 0..5 {
+        app.create_confirmed_subscriber().await;
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let drain = || async {
+        while try_execute_task(
+            &app.db_pool,
+            &app.email_client,
+            &app.base_url,
+            &app.token_settings,
+        )
+        .await
+        .unwrap()
+            == ExecutionOutcome::TaskCompleted
+        {}
+    };
+    tokio::join!(drain(), drain(), drain());
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // The mock verifies each subscriber received the issue exactly once.
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "body_text": "Newsletter body as plain text",
+                "body_html": "<p>Newsletter body as HTML</p>",
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_newsletters(body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn anonymous_requests_are_redirected_to_login() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_body()).await;
+
+    // Assert
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(response.headers()["Location"], "/login");
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letters = sqlx::query!("SELECT dead_letter_id FROM email_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(dead_letters.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_out_of_retries_are_dead_lettered() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letter = sqlx::query!("SELECT subject, attempts FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.subject, "Newsletter title");
+    assert_eq!(dead_letter.attempts, 3);
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_not_retried() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letter = sqlx::query!("SELECT attempts FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.attempts, 1);
+}
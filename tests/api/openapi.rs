@@ -0,0 +1,65 @@
+use crate::setup::TestApp;
+
+impl TestApp {
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}:{}{}", self.app_address, self.app_port, path))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_public_endpoints() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.get("/api/openapi.json").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    for path in [
+        "/subscriptions",
+        "/subscriptions/confirm",
+        "/subscriptions/unsubscribe",
+        "/api/v1/subscriptions",
+    ] {
+        assert!(document["paths"][path].is_object(), "{} is missing", path);
+    }
+    let schemas = &document["components"]["schemas"];
+    for schema in ["FormData", "SubscriptionRequest", "ErrorBody"] {
+        assert!(schemas[schema].is_object(), "{} is missing", schema);
+    }
+    let confirm_parameters = &document["paths"]["/subscriptions/confirm"]["get"]["parameters"];
+    assert_eq!(confirm_parameters[0]["name"], "token");
+    assert_eq!(confirm_parameters[0]["in"], "query");
+}
+
+#[tokio::test]
+async fn docs_ui_is_served_when_enabled() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.api_docs.serve_ui = true).await;
+
+    // Act
+    let response = app.get("/api/docs/").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("swagger"));
+}
+
+#[tokio::test]
+async fn docs_ui_is_not_served_when_disabled() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| c.api_docs.serve_ui = false).await;
+
+    // Act
+    let response = app.get("/api/docs/").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
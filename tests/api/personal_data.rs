@@ -0,0 +1,245 @@
+use crate::newsletters::newsletter_body;
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    async fn post_admin_data_request(&self, action: &str, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/subscribers/data_requests/{}",
+                self.app_address, self.app_port, action
+            ))
+            .json(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    async fn only_subscriber_email(&self) -> String {
+        sqlx::query!("SELECT email FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .email
+    }
+
+    /// Count the rows left about subscribers, across every table holding them.
+    async fn subscriber_rows(&self) -> i64 {
+        sqlx::query!(
+            r#"SELECT
+                (SELECT COUNT(*) FROM subscriptions)
+                + (SELECT COUNT(*) FROM list_subscriptions)
+                + (SELECT COUNT(*) FROM tokens)
+                + (SELECT COUNT(*) FROM management_tokens)
+                + (SELECT COUNT(*) FROM issue_delivery_queue)
+                + (SELECT COUNT(*) FROM email_dead_letters) AS "count!""#
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .unwrap()
+        .count
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_export_their_data() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let email = app.only_subscriber_email().await;
+
+    // Act
+    let response = reqwest::get(link.replace("/manage?", "/manage/export?"))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], email.as_str());
+    assert_eq!(export["lists"][0]["list"], "default");
+    assert_eq!(export["lists"][0]["status"], "confirmed");
+    assert_eq!(export["tokens"].as_array().unwrap().len(), 1);
+    assert!(export["tokens"][0].get("token_hash").is_none());
+    assert!(export["management_link_issued_at"].is_string());
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let email = app.only_subscriber_email().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(link.replace("/manage?", "/manage/erase?"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_rows().await, 0);
+    let tombstone =
+        sqlx::query!("SELECT email_hash, requested_by, admin_user_id FROM erased_subscribers")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(tombstone.requested_by, "subscriber");
+    assert!(tombstone.admin_user_id.is_none());
+    assert!(!tombstone.email_hash.contains(&email));
+    assert_eq!(reqwest::get(link).await.unwrap().status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn erasure_covers_the_notice_to_a_changed_address() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let old_email = app.only_subscriber_email().await;
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let response = app.post_email_change(&link, "new@example.com").await;
+        assert_eq!(response.status().as_u16(), 303);
+    }
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    {
+        // The notice to the old address can't be delivered.
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        reqwest::get(app.get_links(&email_request).html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    let dead_letter = sqlx::query!("SELECT recipient FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.recipient, old_email);
+    let export: serde_json::Value = reqwest::get(link.replace("/manage?", "/manage/export?"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(export["failed_deliveries"].as_array().unwrap().len(), 1);
+
+    // Act
+    let response = app
+        .api_client
+        .post(link.replace("/manage?", "/manage/erase?"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_rows().await, 0);
+}
+
+#[tokio::test]
+async fn admins_can_export_a_subscriber_by_address() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.only_subscriber_email().await;
+
+    // Act
+    let response = app
+        .post_admin_data_request("export", &email.to_uppercase())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], email.as_str());
+}
+
+#[tokio::test]
+async fn admins_can_erase_a_subscriber_by_address() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.only_subscriber_email().await;
+    app.post_newsletters(newsletter_body()).await;
+
+    // Act
+    let response = app.post_admin_data_request("erase", &email).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["tombstone_id"].is_string());
+    assert_eq!(app.subscriber_rows().await, 0);
+    let tombstone = sqlx::query!(
+        "SELECT t.requested_by, u.username FROM erased_subscribers t
+        JOIN users u ON u.user_id = t.admin_user_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tombstone.requested_by, "admin");
+    assert_eq!(tombstone.username, app.test_user.username);
+    let again = app.post_admin_data_request("erase", &email).await;
+    assert_eq!(again.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admin_data_requests_for_unknown_addresses_are_not_found() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+
+    for action in ["export", "erase"] {
+        // Act
+        let response = app
+            .post_admin_data_request(action, "nobody@example.com")
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404, "{}", action);
+    }
+}
+
+#[tokio::test]
+async fn admin_data_requests_require_login() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.only_subscriber_email().await;
+
+    for action in ["export", "erase"] {
+        // Act
+        let response = app.post_admin_data_request(action, &email).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 303, "{}", action);
+    }
+    assert!(app.subscriber_rows().await > 0);
+}
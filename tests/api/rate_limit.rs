@@ -0,0 +1,151 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::{RateLimitStoreKind, Settings};
+
+fn with_limits(ip_capacity: u32, email_capacity: u32) -> impl FnOnce(&mut Settings) {
+    move |c| {
+        c.rate_limit.per_ip.capacity = ip_capacity;
+        c.rate_limit.per_email.capacity = email_capacity;
+    }
+}
+
+async fn mount_email_server(app: &TestApp, expected_sends: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_sends)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn repeated_subscriptions_for_an_email_are_limited() {
+    // Arrange
+    let app = TestApp::spawn_with(with_limits(100, 1)).await;
+    mount_email_server(&app, 1).await;
+    let body = "name=Test%20User&email=test@example.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "rate_limited");
+}
+
+#[tokio::test]
+async fn email_limits_are_shared_by_the_form_and_the_api() {
+    // Arrange
+    let app = TestApp::spawn_with(with_limits(100, 1)).await;
+    mount_email_server(&app, 1).await;
+    app.post_subscriptions("name=Test%20User&email=test@example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": " TEST@example.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn subscriptions_from_one_ip_are_limited() {
+    // Arrange
+    let app = TestApp::spawn_with(with_limits(2, 100)).await;
+    mount_email_server(&app, 2).await;
+
+    for i in 0..2 {
+        let body = format!("name=Test%20User&email=test{}@example.com", i);
+        app.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Test%20User&email=other@example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarded_ips_are_only_used_when_trusted() {
+    for (trusted, expected_status, expected_sends) in [(true, 200, 2), (false, 429, 1)] {
+        // Arrange
+        let app = TestApp::spawn_with(move |c| {
+            with_limits(1, 100)(c);
+            c.rate_limit.trust_forwarded_for = trusted;
+        })
+        .await;
+        mount_email_server(&app, expected_sends).await;
+        let post_from = |ip: &'static str, email: &'static str| {
+            reqwest::Client::new()
+                .post(format!(
+                    "{}:{}/subscriptions",
+                    app.app_address, app.app_port
+                ))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .header("X-Forwarded-For", ip)
+                .body(format!("name=Test%20User&email={}", email))
+                .send()
+        };
+        post_from("203.0.113.1", "first@example.com").await.unwrap();
+
+        // Act
+        let response = post_from("203.0.113.2", "second@example.com")
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "trust_forwarded_for: {}",
+            trusted
+        );
+    }
+}
+
+#[tokio::test]
+async fn memory_store_enforces_limits() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| {
+        with_limits(100, 1)(c);
+        c.rate_limit.store = RateLimitStoreKind::Memory;
+    })
+    .await;
+    mount_email_server(&app, 1).await;
+    let body = "name=Test%20User&email=test@example.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
@@ -1,19 +1,31 @@
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::startup::AppInfo;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::create_user;
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailTransportKind, Settings, TokenSettings,
+};
+use zero2prod::confirmation_worker::try_execute_confirmation_task;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::mail::EmailClient;
+use zero2prod::routes::unsubscribe_link;
+use zero2prod::startup::{AppBaseUrl, AppInfo};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 static SUBSCRIBER: Lazy<()> = Lazy::new(|| {
     let name = "Testing".into();
     let level = "debug".into();
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(name, level, std::io::stdout);
+        let subscriber = get_subscriber(name, level, std::io::stdout, None);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(name, level, std::io::sink);
+        let subscriber = get_subscriber(name, level, std::io::sink, None);
         init_subscriber(subscriber);
     };
 });
@@ -23,11 +35,41 @@ pub struct TestApp {
     pub app_port: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub base_url: AppBaseUrl,
+    pub token_settings: TokenSettings,
+    pub test_user: TestUser,
+    /// Client that keeps cookies between requests and doesn't follow redirects.
+    pub api_client: reqwest::Client,
+}
+
+/// An admin user created for each test app.
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    async fn store(pool: &PgPool) -> TestUser {
+        let user = TestUser {
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        };
+        create_user(&user.username, Secret::new(user.password.clone()), pool)
+            .await
+            .expect("Failed to create test user");
+        user
+    }
 }
 
 impl TestApp {
-    #[tracing::instrument(name = "Spawning Test Server")]
     pub async fn spawn_new() -> TestApp {
+        Self::spawn_with(|_| {}).await
+    }
+
+    /// Spawn a test app, letting the caller adjust the configuration first.
+    #[tracing::instrument(name = "Spawning Test Server", skip(customize))]
+    pub async fn spawn_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
         // Setup Telemetry (once.)
         Lazy::force(&SUBSCRIBER);
 
@@ -39,15 +81,21 @@ impl TestApp {
             let mut c = get_configuration().expect("Failed to get Configuration");
             c.database.name = Uuid::new_v4().to_string();
             c.app.port = "0".into();
+            c.email_client.transport = EmailTransportKind::Postmark;
             c.email_client.base_url = email_server.uri();
+            customize(&mut c);
             c
         };
 
         let db_connection = configure_database(&configuration.database).await;
+        let test_user = TestUser::store(&db_connection).await;
+        let email_client = configuration.email_client.client();
+        let base_url = AppBaseUrl(configuration.app.base_url.clone());
+        let token_settings = configuration.tokens.clone();
 
         // Spawn app
         let app = AppInfo::new(configuration, db_connection.clone()).expect("Failed to build app");
-        let _ = tokio::spawn(app.server);
+        tokio::spawn(app.server);
         tracing::info!("App Address: {}", app.app_address);
 
         TestApp {
@@ -55,6 +103,15 @@ impl TestApp {
             app_port: app.app_port,
             db_pool: db_connection,
             email_server,
+            email_client,
+            base_url,
+            token_settings,
+            test_user,
+            api_client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .cookie_store(true)
+                .build()
+                .unwrap(),
         }
     }
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
@@ -69,6 +126,161 @@ impl TestApp {
             .await
             .expect("Sending request failed!")
     }
+    pub async fn post_api_subscriptions(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}:{}/api/v1/subscriptions",
+                self.app_address, self.app_port
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+    /// Drain the delivery queue, including tasks currently held by the app's own worker.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.token_settings,
+            )
+            .await
+            .expect("Delivery task failed");
+            if outcome == ExecutionOutcome::EmptyQueue {
+                let remaining = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+                    .fetch_one(&self.db_pool)
+                    .await
+                    .expect("Failed to query delivery queue");
+                if remaining.count == Some(0) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+    }
+    /// Drain the confirmation queue, including tasks currently held by the app's own worker.
+    pub async fn dispatch_all_pending_confirmations(&self) {
+        loop {
+            let outcome = try_execute_confirmation_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.token_settings,
+            )
+            .await
+            .expect("Confirmation task failed");
+            if outcome == ExecutionOutcome::EmptyQueue {
+                let remaining =
+                    sqlx::query!("SELECT COUNT(*) AS count FROM confirmation_email_queue")
+                        .fetch_one(&self.db_pool)
+                        .await
+                        .expect("Failed to query confirmation queue");
+                if remaining.count == Some(0) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+    }
+    pub async fn post_login(&self, username: &str, password: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}:{}/login", self.app_address, self.app_port))
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+    /// Log the API client in as the test user.
+    pub async fn log_in_as_admin(&self) {
+        let response = self
+            .post_login(&self.test_user.username, &self.test_user.password)
+            .await;
+        assert_eq!(response.status().as_u16(), 303, "Logging in failed");
+    }
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}:{}/admin/newsletters",
+                self.app_address, self.app_port
+            ))
+            .json(&body)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+    /// Subscribe a fresh user without confirming them, returning their confirmation links.
+    pub async fn create_unconfirmed_subscriber(&self) -> ConfirmationLinks {
+        let user_name: String = Name().fake();
+        let user_email: String = SafeEmail().fake();
+        let payload = format!("name={}&email={}", user_name, user_email);
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(payload)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_links(&email_request)
+    }
+
+    /// Subscribe a fresh user and follow their confirmation link.
+    pub async fn create_confirmed_subscriber(&self) {
+        let links = self.create_unconfirmed_subscriber().await;
+        self.confirm(&links.html).await;
+    }
+
+    /// Follow a confirmation link, returning the confirmation page. The welcome email sent on
+    /// confirmation is accepted by the mail server.
+    pub async fn confirm(&self, link: &str) -> String {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Welcome confirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        reqwest::get(link)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// The signed link unsubscribing the subscriber with the given address from the list, as sent
+    /// with newsletter issues.
+    pub async fn unsubscribe_link(&self, email: &str, list: &str) -> String {
+        let ids = sqlx::query!(
+            "SELECT s.id, l.list_id FROM subscriptions s, lists l
+            WHERE s.email = $1 AND l.slug = $2",
+            email,
+            list
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to query subscriber");
+        let link = unsubscribe_link(ids.id, ids.list_id, &self.token_settings, &self.base_url);
+        let mut link = reqwest::Url::parse(&link).unwrap();
+        link.set_port(Some(self.app_port.parse().unwrap())).unwrap();
+        link.into()
+    }
     pub fn get_links(&self, request: &wiremock::Request) -> ConfirmationLinks {
         let get_link = |s: &str| -> String {
             let links: Vec<_> = linkify::LinkFinder::new()
@@ -0,0 +1,184 @@
+use crate::setup::TestApp;
+
+impl TestApp {
+    async fn get_subscriber_export(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}:{}/admin/subscribers/export",
+                self.app_address, self.app_port
+            ))
+            .query(query)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    /// Import two confirmed subscribers to the default list, and one pending subscriber to the
+    /// `weekly` list.
+    async fn seed_subscribers(&self) {
+        self.create_list("weekly").await;
+        let confirmed = [("status", "confirmed"), ("attestation", "Old signup form")];
+        let response = self
+            .post_import(
+                &confirmed,
+                "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n".into(),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+        let response = self
+            .post_import(
+                &[("status", "pending"), ("list", "weekly")],
+                "email,name\nursula@example.com,Ursula\n".into(),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.seed_subscribers().await;
+
+    // Act
+    let response = app.get_subscriber_export(&[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    // The body is streamed, so its length isn't known up front.
+    assert_eq!(response.content_length(), None);
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(
+        lines.next(),
+        Some("email,name,list,status,subscribed_at,unsubscribed_at")
+    );
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), 3);
+    assert!(rows
+        .iter()
+        .any(|row| row.starts_with("ursula@example.com,Ursula,weekly,pending,")));
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered_by_status_and_list() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.seed_subscribers().await;
+    let test_cases = [
+        (vec![("status", "confirmed")], 2, "confirmed"),
+        (vec![("status", "unsubscribed")], 0, "unsubscribed"),
+        (vec![("list", "weekly")], 1, "weekly list"),
+        (
+            vec![("list", "default"), ("status", "pending")],
+            0,
+            "pending on the default list",
+        ),
+    ];
+
+    for (mut query, expected, description) in test_cases {
+        query.push(("format", "jsonl"));
+
+        // Act
+        let response = app.get_subscriber_export(&query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200, "{}", description);
+        assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+        let body = response.text().await.unwrap();
+        let rows: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), expected, "{}", description);
+        for row in rows {
+            if let Some((_, status)) = query.iter().find(|(key, _)| *key == "status") {
+                assert_eq!(row["status"], *status, "{}", description);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered_by_date_range() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.seed_subscribers().await;
+    sqlx::query!(
+        "UPDATE list_subscriptions SET subscribed_at = '2021-06-15T00:00:00Z'
+        WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = 'octavia@example.com')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let old = app
+        .get_subscriber_export(&[
+            ("format", "jsonl"),
+            ("from", "2021-01-01T00:00:00Z"),
+            ("to", "2022-01-01T00:00:00Z"),
+        ])
+        .await;
+    let recent = app
+        .get_subscriber_export(&[("format", "jsonl"), ("from", "2022-01-01T00:00:00Z")])
+        .await;
+
+    // Assert
+    let old = old.text().await.unwrap();
+    assert_eq!(old.lines().count(), 1);
+    assert!(old.contains("octavia@example.com"));
+    let recent = recent.text().await.unwrap();
+    assert_eq!(recent.lines().count(), 2);
+    assert!(!recent.contains("octavia@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_export_queries_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let test_cases = [
+        (vec![("format", "xml")], 400, "unknown format"),
+        (vec![("status", "bounced")], 400, "unknown status"),
+        (vec![("from", "yesterday")], 400, "invalid date"),
+        (
+            vec![
+                ("from", "2022-02-01T00:00:00Z"),
+                ("to", "2022-01-01T00:00:00Z"),
+            ],
+            400,
+            "backwards date range",
+        ),
+        (vec![("list", "missing")], 404, "unknown list"),
+    ];
+
+    for (query, status, description) in test_cases {
+        // Act
+        let response = app.get_subscriber_export(&query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), status, "{}", description);
+    }
+}
+
+#[tokio::test]
+async fn subscriber_exports_require_login() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.create_confirmed_subscriber().await;
+
+    // Act
+    let response = app.get_subscriber_export(&[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+}
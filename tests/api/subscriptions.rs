@@ -1,4 +1,9 @@
+mod api;
 mod basic_ops;
+mod bot_protection;
+mod change_email;
 mod confirmation;
 mod data_validation;
 mod email;
+mod manage;
+mod unsubscribe;
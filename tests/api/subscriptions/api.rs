@@ -0,0 +1,196 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn new_subscribers_are_created() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": "test@example.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["outcome"], "created");
+    let saved = sqlx::query!("SELECT s.email, l.status FROM subscriptions s JOIN list_subscriptions l ON l.subscriber_id = s.id")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "test@example.com");
+    assert_eq!(saved.status, "pending");
+}
+
+#[tokio::test]
+async fn pending_subscribers_get_a_fresh_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({"name": "Test User", "email": "test@example.com"});
+    app.post_api_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["outcome"], "already_pending");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_emailed_again() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({"name": "Test User", "email": email}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["outcome"], "already_confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_addresses_are_subscribed_again() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.create_confirmed_subscriber().await;
+    let email = app.subscriber_email().await;
+    let link = app.unsubscribe_link(&email, "default").await;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_api_subscriptions(&serde_json::json!({"name": "Test User", "email": email}))
+            .await
+    };
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["outcome"], "created");
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending");
+    assert_eq!(saved.unsubscribed_at, None);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm(&app.get_links(&email_request).html).await;
+}
+
+#[tokio::test]
+async fn every_invalid_field_is_reported() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "", "email": "not-an-email"}),
+            vec!["name", "email"],
+        ),
+        (
+            serde_json::json!({"name": "Test User", "email": "not-an-email"}),
+            vec!["email"],
+        ),
+        (
+            serde_json::json!({"email": "test@example.com"}),
+            vec!["name"],
+        ),
+        (serde_json::json!({}), vec!["name", "email"]),
+    ];
+
+    for (payload, invalid_fields) in test_cases {
+        // Act
+        let response = app.post_api_subscriptions(&payload).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{}", payload);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "validation_error");
+        let fields = body["fields"].as_object().unwrap();
+        assert_eq!(fields.len(), invalid_fields.len(), "{}", payload);
+        for field in invalid_fields {
+            assert!(fields.contains_key(field), "{} in {}", field, payload);
+        }
+    }
+}
+
+#[tokio::test]
+async fn malformed_json_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}:{}/api/v1/subscriptions",
+            app.app_address, app.app_port
+        ))
+        .header("Content-Type", "application/json")
+        .body("{\"name\": ")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_error");
+}
@@ -34,7 +34,7 @@ async fn subscriber_data_is_saved() {
     //Act
     let body = "name=Test%20User&email=test@example.com";
     let response = app.post_subscriptions(body.into()).await;
-    let record = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    let record = sqlx::query!("SELECT s.email, s.name, l.status FROM subscriptions s JOIN list_subscriptions l ON l.subscriber_id = s.id")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to query database");
@@ -69,3 +69,35 @@ async fn form_post_fails_correctly_with_missing_data() {
         );
     }
 }
+
+#[tokio::test]
+async fn addresses_differing_only_in_case_are_one_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=Test%20User&email=Test.User@Example.COM".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Test%20User&email=%20test.user@example.com%20".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    // The local part keeps the case it was first typed in, for sending.
+    assert_eq!(saved[0].email, "Test.User@example.com");
+    assert_eq!(saved[0].email_canonical, "test.user@example.com");
+}
@@ -0,0 +1,233 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::bot_protection::solve_proof_of_work;
+use zero2prod::configuration::Settings;
+
+impl TestApp {
+    /// Fetch a nonce for the subscription form, along with the proof-of-work difficulty.
+    pub async fn get_form_challenge(&self) -> (String, u8) {
+        let challenge: serde_json::Value = reqwest::get(format!(
+            "{}:{}/subscriptions/challenge",
+            self.app_address, self.app_port
+        ))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        (
+            challenge["nonce"].as_str().unwrap().to_owned(),
+            challenge["pow_difficulty"].as_u64().unwrap() as u8,
+        )
+    }
+
+    async fn subscriber_count(&self) -> i64 {
+        sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .count
+            .unwrap()
+    }
+}
+
+fn requiring_nonce(c: &mut Settings) {
+    c.bot_protection.require_nonce = true;
+    c.bot_protection.min_fill_secs = 0;
+}
+
+async fn mount_email_server(app: &TestApp, expected_sends: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_sends)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn filled_honeypots_are_dropped_silently() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    mount_email_server(&app, 0).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=Test%20User&email=test@example.com&website=http://spam.example.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_count().await, 0);
+}
+
+#[tokio::test]
+async fn submissions_with_a_valid_nonce_are_accepted() {
+    // Arrange
+    let app = TestApp::spawn_with(requiring_nonce).await;
+    mount_email_server(&app, 1).await;
+    let (nonce, _) = app.get_form_challenge().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=Test%20User&email=test@example.com&nonce={}",
+            nonce
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_count().await, 1);
+}
+
+#[tokio::test]
+async fn submissions_without_a_required_nonce_are_dropped() {
+    // Arrange
+    let app = TestApp::spawn_with(requiring_nonce).await;
+    mount_email_server(&app, 0).await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Test%20User&email=test@example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_count().await, 0);
+}
+
+#[tokio::test]
+async fn api_requests_without_a_required_nonce_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_with(requiring_nonce).await;
+    mount_email_server(&app, 0).await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": "test@example.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(app.subscriber_count().await, 0);
+}
+
+#[tokio::test]
+async fn api_requests_with_a_valid_nonce_are_accepted() {
+    // Arrange
+    let app = TestApp::spawn_with(requiring_nonce).await;
+    mount_email_server(&app, 1).await;
+    let (nonce, _) = app.get_form_challenge().await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": "test@example.com",
+            "nonce": nonce,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(app.subscriber_count().await, 1);
+}
+
+#[tokio::test]
+async fn replayed_nonces_are_dropped() {
+    // Arrange
+    let app = TestApp::spawn_with(requiring_nonce).await;
+    mount_email_server(&app, 1).await;
+    let (nonce, _) = app.get_form_challenge().await;
+    app.post_subscriptions(format!(
+        "name=Test%20User&email=first@example.com&nonce={}",
+        nonce
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=Test%20User&email=second@example.com&nonce={}",
+            nonce
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_count().await, 1);
+}
+
+#[tokio::test]
+async fn submissions_faster_than_a_human_are_dropped() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| {
+        c.bot_protection.require_nonce = true;
+        c.bot_protection.min_fill_secs = 60;
+    })
+    .await;
+    mount_email_server(&app, 0).await;
+    let (nonce, _) = app.get_form_challenge().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=Test%20User&email=test@example.com&nonce={}",
+            nonce
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_count().await, 0);
+}
+
+#[tokio::test]
+async fn proof_of_work_is_checked_when_enabled() {
+    // Arrange
+    let app = TestApp::spawn_with(|c| {
+        requiring_nonce(c);
+        c.bot_protection.pow_difficulty = 8;
+    })
+    .await;
+    mount_email_server(&app, 1).await;
+    let (nonce, difficulty) = app.get_form_challenge().await;
+    assert_eq!(difficulty, 8);
+    let (unsolved_nonce, _) = app.get_form_challenge().await;
+
+    // Act
+    let solved = app
+        .post_subscriptions(format!(
+            "name=Test%20User&email=solved@example.com&nonce={}&pow={}",
+            nonce,
+            solve_proof_of_work(&nonce, difficulty)
+        ))
+        .await;
+    let unsolved = app
+        .post_subscriptions(format!(
+            "name=Test%20User&email=unsolved@example.com&nonce={}",
+            unsolved_nonce
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(solved.status().as_u16(), 200);
+    assert_eq!(unsolved.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "solved@example.com");
+}
@@ -0,0 +1,192 @@
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    pub async fn post_email_change(&self, management_link: &str, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(management_link.replace("/manage?", "/manage/email?"))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    pub async fn subscriber_email(&self) -> String {
+        sqlx::query!("SELECT email FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .email
+    }
+
+    /// The recipients of every email sent so far, in order.
+    async fn email_recipients(&self) -> Vec<String> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                body["To"].as_str().unwrap().to_owned()
+            })
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn address_only_changes_once_the_new_address_is_confirmed() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let old_email = app.subscriber_email().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Request the change
+    let response = app.post_email_change(&link, "new@example.com").await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(app.subscriber_email().await, old_email);
+    let page = reqwest::get(&link).await.unwrap().text().await.unwrap();
+    assert!(page.contains("new@example.com"));
+
+    // Act - Part 2 - Confirm the new address
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirm_link = app.get_links(&email_request).html;
+    let response = reqwest::get(&confirm_link).await.unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_email().await, "new@example.com");
+    let recipients = app.email_recipients().await;
+    assert_eq!(
+        recipients[recipients.len() - 2..],
+        ["new@example.com", &old_email]
+    );
+    // Change tokens are single use, and can't unsubscribe.
+    assert_eq!(
+        reqwest::get(&confirm_link).await.unwrap().status().as_u16(),
+        401
+    );
+    let unsubscribe_link = confirm_link.replace("/confirm?", "/unsubscribe?");
+    assert_eq!(
+        reqwest::get(unsubscribe_link)
+            .await
+            .unwrap()
+            .status()
+            .as_u16(),
+        401
+    );
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let own_email = app.subscriber_email().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (email, description) in [
+        (own_email.as_str(), "current address"),
+        ("not-an-email", "invalid address"),
+        ("someone@mailinator.com", "disposable domain"),
+    ] {
+        // Act
+        let response = app.post_email_change(&link, email).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{}", description);
+    }
+}
+
+#[tokio::test]
+async fn subscribed_addresses_are_only_refused_on_confirmation() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let own_email = app.subscriber_email().await;
+    app.create_unconfirmed_subscriber().await;
+    let other_email = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE email <> $1",
+        own_email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .email;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Request the change
+    let response = app.post_email_change(&link, &other_email).await;
+
+    // Assert - Part 1
+    // The answer doesn't tell that the address is taken.
+    assert_eq!(response.status().as_u16(), 303);
+
+    // Act - Part 2 - Confirm the new address
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let response = reqwest::get(app.get_links(&email_request).html)
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 409);
+    let emails: Vec<String> = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.email)
+        .collect();
+    assert!(emails.contains(&own_email));
+    assert!(emails.contains(&other_email));
+}
+
+#[tokio::test]
+async fn email_change_requires_a_management_token() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_email_change(
+            &format!(
+                "{}:{}/subscriptions/manage?token=notarealtoken",
+                app.app_address, app.app_port
+            ),
+            "new@example.com",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
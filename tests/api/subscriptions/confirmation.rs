@@ -6,15 +6,18 @@ use rand::distributions::Alphanumeric;
 use rand::Rng;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::routes::hash_legacy_tokens;
 
 #[tokio::test]
 pub async fn clicking_email_link_confirms_subscriber() {
     // Arrange
     let app = TestApp::spawn_new().await;
+    // The confirmation, then the welcome.
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .expect(1)
+        .expect(2)
         .mount(&app.email_server)
         .await;
     let user_name: String = Name().fake();
@@ -33,7 +36,7 @@ pub async fn clicking_email_link_confirms_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+    let saved = sqlx::query!("SELECT s.email, s.name, l.status FROM subscriptions s JOIN list_subscriptions l ON l.subscriber_id = s.id")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to run query");
@@ -46,10 +49,11 @@ pub async fn clicking_email_link_confirms_subscriber() {
 pub async fn clicking_email_link_twice_yields_correct_db_entry() {
     // Arrange
     let app = TestApp::spawn_new().await;
+    // The confirmation, then a single welcome.
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .expect(1)
+        .expect(2)
         .mount(&app.email_server)
         .await;
     let user_name: String = Name().fake();
@@ -73,7 +77,7 @@ pub async fn clicking_email_link_twice_yields_correct_db_entry() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+    let saved = sqlx::query!("SELECT s.email, s.name, l.status FROM subscriptions s JOIN list_subscriptions l ON l.subscriber_id = s.id")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to run query");
@@ -103,16 +107,19 @@ pub async fn bad_access_token_fails_gracefully() {
 
     // Assert
     assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_token");
 }
 
 #[tokio::test]
 pub async fn extracted_link_from_confirm_email_returns_200() {
     // Arrange
     let app = TestApp::spawn_new().await;
+    // The confirmation, then the welcome.
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .expect(1)
+        .expect(2)
         .mount(&app.email_server)
         .await;
 
@@ -155,3 +162,118 @@ pub async fn confirm_returns_400_with_no_token() {
     // Assert
     assert_eq!(response.status().as_u16(), 400);
 }
+
+#[tokio::test]
+pub async fn expired_token_returns_410() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let user_name: String = Name().fake();
+    let user_email: String = SafeEmail().fake();
+    let payload = format!("name={}&email={}", user_name, user_email);
+
+    app.post_subscriptions(payload).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_links(email_request).html;
+    sqlx::query!("UPDATE tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to expire token");
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+    assert_eq!(saved.status, "pending");
+}
+
+#[tokio::test]
+pub async fn tokens_are_not_stored_in_plaintext() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let user_name: String = Name().fake();
+    let user_email: String = SafeEmail().fake();
+    let payload = format!("name={}&email={}", user_name, user_email);
+
+    // Act
+    app.post_subscriptions(payload).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = reqwest::Url::parse(&app.get_links(email_request).html).unwrap();
+    let (_, token) = link.query_pairs().find(|(k, _)| k == "token").unwrap();
+    let saved = sqlx::query!("SELECT token_hash, hashed FROM tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+    assert_ne!(saved.token_hash, token);
+    assert!(saved.hashed);
+}
+
+#[tokio::test]
+pub async fn legacy_plaintext_tokens_still_confirm_after_hashing() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let subscriber_id = uuid::Uuid::new_v4();
+    let legacy_token = "legacyplaintexttoken00001";
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+        VALUES ($1, 'legacy@example.com', 'legacy@example.com', 'Legacy User', now())",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT list_id, $1, 'pending', now() FROM lists WHERE slug = 'default'",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO tokens (subscriber_id, list_id, token_hash, expires_at)
+        SELECT $1, list_id, $2, now() + interval '1 day' FROM lists WHERE slug = 'default'",
+        subscriber_id,
+        legacy_token
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let settings = get_configuration().unwrap().tokens;
+    let converted = hash_legacy_tokens(&settings, &app.db_pool).await.unwrap();
+    let response = reqwest::get(format!(
+        "{}:{}/subscriptions/confirm?token={}",
+        app.app_address, app.app_port, legacy_token
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(converted, 1);
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT token_hash FROM tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, legacy_token);
+}
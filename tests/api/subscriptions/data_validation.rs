@@ -1,4 +1,6 @@
 use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn subscribe_fails_on_empty_required_fields() {
@@ -24,3 +26,110 @@ async fn subscribe_fails_on_empty_required_fields() {
         )
     }
 }
+
+#[tokio::test]
+async fn validation_errors_are_reported_as_json() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Tommy&email=not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_error");
+    assert!(!body["message"].as_str().unwrap().is_empty());
+    let request_id = body["request_id"].as_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok(), "{}", request_id);
+}
+
+#[tokio::test]
+async fn missing_fields_are_reported_as_validation_errors() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.post_subscriptions("name=Tommy".into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_error");
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected_with_a_distinct_error() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Tommy&email=tommy@mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "email_domain_rejected");
+    assert_eq!(
+        body["fields"]["email"],
+        "Disposable email addresses are not accepted."
+    );
+}
+
+#[tokio::test]
+async fn blocklisted_domains_are_rejected_by_the_api_too() {
+    // Arrange
+    let app =
+        TestApp::spawn_with(|c| c.email_domains.blocklist = vec!["blocked.test".into()]).await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "Tommy",
+            "email": "tommy@mail.Blocked.test",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "email_domain_rejected");
+}
+
+#[tokio::test]
+async fn disposable_list_can_be_replaced_from_a_file() {
+    // Arrange
+    let list = std::env::temp_dir().join(format!("disposable-{}.txt", uuid::Uuid::new_v4()));
+    std::fs::write(&list, "# Our own list\nthrowaway.test\n").unwrap();
+    let list_path = list.to_str().unwrap().to_owned();
+    let app = TestApp::spawn_with(|c| c.email_domains.disposable_list_path = Some(list_path)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let custom = app
+        .post_subscriptions("name=Tommy&email=tommy@throwaway.test".into())
+        .await;
+    let bundled = app
+        .post_subscriptions("name=Tommy&email=tommy@mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(custom.status().as_u16(), 400);
+    // The file replaces the bundled list, so its domains are no longer rejected.
+    assert_eq!(bundled.status().as_u16(), 200);
+    std::fs::remove_file(list).unwrap();
+}
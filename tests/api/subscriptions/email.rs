@@ -70,3 +70,67 @@ pub async fn subscribing_twice_sends_dual_emails() {
         assert_eq!(response.status(), 200);
     }
 }
+
+#[tokio::test]
+pub async fn subscribing_again_with_expired_token_sends_fresh_token() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let name = "Fake Name";
+    let email = SafeEmail().fake::<String>();
+    let body = format!("name={}&email={}", name, email);
+
+    // Two confirmations, then the welcome.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.clone()).await;
+    sqlx::query!("UPDATE tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to expire token");
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let stale_link = app.get_links(&requests[0]).html;
+    let fresh_link = app.get_links(&requests[1]).html;
+    assert_ne!(stale_link, fresh_link);
+    assert_eq!(reqwest::get(stale_link).await.unwrap().status(), 401);
+    assert_eq!(reqwest::get(fresh_link).await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+pub async fn subscribing_again_replaces_previous_token() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let name = "Fake Name";
+    let email = SafeEmail().fake::<String>();
+    let body = format!("name={}&email={}", name, email);
+
+    // Two confirmations, then the welcome.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.clone()).await;
+    app.post_subscriptions(body).await;
+
+    // Assert
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_links(&requests[0]).html;
+    let second_link = app.get_links(&requests[1]).html;
+    assert_ne!(first_link, second_link);
+    assert_eq!(reqwest::get(first_link).await.unwrap().status(), 401);
+    assert_eq!(reqwest::get(second_link).await.unwrap().status(), 200);
+}
@@ -0,0 +1,299 @@
+use crate::newsletters::newsletter_body;
+use crate::setup::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl TestApp {
+    /// Subscribe and confirm a fresh user, returning the management link from the confirmation
+    /// page.
+    pub async fn confirmed_subscriber_management_link(&self) -> String {
+        let links = self.create_unconfirmed_subscriber().await;
+        let page = self.confirm(&links.html).await;
+        let links: Vec<_> = linkify::LinkFinder::new()
+            .links(&page)
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .collect();
+        assert_eq!(links.len(), 1);
+        let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+        link.set_port(Some(self.app_port.parse().unwrap())).unwrap();
+        link.into()
+    }
+
+    pub async fn post_preferences(&self, link: &str, form: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(link)
+            .form(form)
+            .send()
+            .await
+            .expect("Sending request failed!")
+    }
+
+    async fn list_statuses(&self) -> Vec<(String, String)> {
+        sqlx::query!(
+            "SELECT l.slug, s.status FROM list_subscriptions s
+            JOIN lists l ON l.list_id = s.list_id
+            ORDER BY l.slug"
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.slug, row.status))
+        .collect()
+    }
+}
+
+#[tokio::test]
+async fn confirmation_page_links_to_the_preference_page() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    assert!(response.text().await.unwrap().contains(&email));
+}
+
+#[tokio::test]
+async fn preference_page_rejects_unknown_tokens() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = app.get("/subscriptions/manage?token=notarealtoken").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn management_tokens_are_not_stored_in_plaintext() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let link = app.confirmed_subscriber_management_link().await;
+
+    // Assert
+    let token = reqwest::Url::parse(&link)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    let saved = sqlx::query!("SELECT token_hash, link_id::TEXT FROM management_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash.as_ref(), Some(&token));
+    assert_ne!(saved.link_id.as_ref(), Some(&token));
+}
+
+#[tokio::test]
+async fn management_link_is_emailed_and_stays_the_same() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let welcome = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let welcome: serde_json::Value = serde_json::from_slice(&welcome.body).unwrap();
+    app.log_in_as_admin().await;
+    app.create_list("weekly").await;
+    let email = app.subscriber_email().await;
+
+    // Act
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_api_subscriptions(&serde_json::json!({
+            "name": "Test User",
+            "email": email,
+            "list": "weekly",
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let page = app.confirm(&app.get_links(&email_request).html).await;
+
+    // Assert
+    let token = reqwest::Url::parse(&link)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    assert!(welcome["TextBody"].as_str().unwrap().contains(&token));
+    assert!(page.contains(&token), "{}", page);
+    assert_eq!(reqwest::get(&link).await.unwrap().status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+
+    // Act
+    let response = app
+        .post_preferences(&link, &[("name", "New Name"), ("lists", "default")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "New Name");
+    assert_eq!(
+        app.list_statuses().await,
+        [("default".to_string(), "confirmed".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let before = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    for (form, description) in [
+        (vec![("name", "<script>"), ("lists", "default")], "bad name"),
+        (
+            vec![("name", "Test User"), ("lists", "missing")],
+            "unknown list",
+        ),
+        (
+            vec![("name", "Test User"), ("pause_weeks", "500")],
+            "pause too long",
+        ),
+    ] {
+        // Act
+        let response = app.post_preferences(&link, &form).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{}", description);
+    }
+    let after = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(before.name, after.name);
+}
+
+#[tokio::test]
+async fn subscribers_can_pick_their_lists() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    app.create_list("weekly").await;
+    let link = app.confirmed_subscriber_management_link().await;
+
+    // Act
+    let response = app
+        .post_preferences(&link, &[("name", "Test User"), ("lists", "weekly")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        app.list_statuses().await,
+        [
+            ("default".to_string(), "unsubscribed".to_string()),
+            ("weekly".to_string(), "confirmed".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn paused_subscribers_are_skipped_until_they_resume() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    app.log_in_as_admin().await;
+    let link = app.confirmed_subscriber_management_link().await;
+    let queued = || async {
+        sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count
+    };
+
+    // Act
+    app.post_preferences(
+        &link,
+        &[
+            ("name", "Test User"),
+            ("lists", "default"),
+            ("pause_weeks", "4"),
+        ],
+    )
+    .await;
+    app.post_newsletters(newsletter_body()).await;
+    let while_paused = queued().await;
+    app.post_preferences(
+        &link,
+        &[
+            ("name", "Test User"),
+            ("lists", "default"),
+            ("pause_weeks", "0"),
+        ],
+    )
+    .await;
+    app.post_newsletters(newsletter_body()).await;
+    let after_resuming = queued().await;
+
+    // Assert
+    assert_eq!(while_paused, 0);
+    assert_eq!(after_resuming, 1);
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_from_everything() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = app.confirmed_subscriber_management_link().await;
+
+    // Act
+    let response = app
+        .post_preferences(&link, &[("action", "unsubscribe")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        app.list_statuses().await,
+        [("default".to_string(), "unsubscribed".to_string())]
+    );
+}
@@ -0,0 +1,99 @@
+use crate::setup::TestApp;
+
+/// Subscribe a fresh user, confirm them, and return the unsubscribe link for that user.
+async fn confirmed_subscriber_unsubscribe_link(app: &TestApp) -> String {
+    app.create_confirmed_subscriber().await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    app.unsubscribe_link(&email, "default").await
+}
+
+#[tokio::test]
+pub async fn unsubscribe_link_unsubscribes_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = confirmed_subscriber_unsubscribe_link(&app).await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+pub async fn one_click_post_unsubscribes_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = confirmed_subscriber_unsubscribe_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+pub async fn unsubscribing_twice_keeps_first_timestamp() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+    let link = confirmed_subscriber_unsubscribe_link(&app).await;
+    reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let first = sqlx::query!("SELECT unsubscribed_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let second = sqlx::query!("SELECT unsubscribed_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to run query");
+    assert_eq!(first.unsubscribed_at, second.unsubscribed_at);
+}
+
+#[tokio::test]
+pub async fn unsubscribe_with_bad_token_returns_401() {
+    // Arrange
+    let app = TestApp::spawn_new().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}:{}/subscriptions/unsubscribe?token=notarealtoken",
+        app.app_address, app.app_port
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,45 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::TelemetrySettings;
+use zero2prod::telemetry::{get_otlp_tracer, get_subscriber, shutdown_tracing};
+
+// The batch exporter runs on the Tokio runtime while `shutdown_tracing` blocks waiting for it, so
+// this needs more than one worker thread.
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_otlp_collector() {
+    // Arrange
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1..)
+        .mount(&collector)
+        .await;
+    let settings = TelemetrySettings {
+        otlp_endpoint: Some(collector.uri()),
+        otlp_timeout_secs: 5,
+    };
+    let tracer = get_otlp_tracer("zero2prod-test", &settings).expect("Failed to build tracer");
+    assert!(tracer.is_some());
+    let subscriber = get_subscriber("test".into(), "info".into(), std::io::sink, tracer);
+
+    // Act
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Exported span").in_scope(|| tracing::info!("Inside the span"));
+    });
+    tokio::task::spawn_blocking(shutdown_tracing).await.unwrap();
+
+    // Assert
+    // The collector stand-in verifies that a batch of spans arrived.
+}
+
+#[test]
+fn no_tracer_is_built_without_a_collector() {
+    let settings = TelemetrySettings {
+        otlp_endpoint: None,
+        otlp_timeout_secs: 5,
+    };
+    assert!(get_otlp_tracer("zero2prod-test", &settings)
+        .expect("Failed to build tracer")
+        .is_none());
+}